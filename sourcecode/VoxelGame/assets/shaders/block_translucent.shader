@@ -0,0 +1,52 @@
+shader_type spatial;
+// Light and ambient occlusion are baked into the vertex colors.
+// Translucent faces are blended with whatever is behind them, and don't hide anything.
+render_mode ambient_light_disabled, blend_mix, depth_draw_never;
+
+// Every block texture tile, each on its own layer.
+uniform sampler2DArray block_textures : hint_albedo;
+// The animation starting on each layer of `block_textures`: how many frames it has,
+// how many seconds each frame lasts, and whether to fade between frames.
+uniform sampler2D block_animations;
+
+// Turns `uv` clockwise around the middle of the tile, `turns` times.
+vec2 rotate_uv(vec2 uv, float turns) {
+	int rotation = int(turns + 0.5);
+	if (rotation == 1) {
+		return vec2(uv.y, 1.0 - uv.x);
+	} else if (rotation == 2) {
+		return vec2(1.0 - uv.x, 1.0 - uv.y);
+	} else if (rotation == 3) {
+		return vec2(1.0 - uv.y, uv.x);
+	}
+	return uv;
+}
+
+// Samples the texture on `layer`, or the current frame of its animation if it has one.
+// Every chunk uses the same `time`, so animations play in sync.
+vec4 sample_block(vec2 uv, float layer, float time) {
+	vec3 animation = texelFetch(block_animations, ivec2(int(layer + 0.5), 0), 0).rgb;
+	float frames = animation.x;
+	if (frames < 1.5) {
+		return texture(block_textures, vec3(uv, layer));
+	}
+	float position = time / animation.y;
+	float frame = mod(floor(position), frames);
+	vec4 albedo = texture(block_textures, vec3(uv, layer + frame));
+	if (animation.z > 0.5) {
+		vec4 next = texture(block_textures, vec3(uv, layer + mod(frame + 1.0, frames)));
+		albedo = mix(albedo, next, fract(position));
+	}
+	return albedo;
+}
+
+void fragment() {
+	// Meshes don't have normals, since every face is flat and this gives the same thing.
+	NORMAL = normalize(cross(dFdx(VERTEX), dFdy(VERTEX)));
+	// UV is measured in blocks, so that the tile repeats across merged faces.
+	// UV2.x is the layer to use from the texture array, and UV2.y how it's rotated.
+	vec2 uv = rotate_uv(fract(UV), UV2.y);
+	vec4 albedo = sample_block(uv, UV2.x, TIME);
+	ALBEDO = albedo.rgb * COLOR.rgb;
+	ALPHA = albedo.a;
+}
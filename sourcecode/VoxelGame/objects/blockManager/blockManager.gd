@@ -1,18 +1,28 @@
 extends Node
 
+const blocks_path = "res://assets/blocks.json";
+
 var blocks_raw = {};
+var module = "";
+var names_to_ids = {};
 var ids_to_names = {};
 
 func _ready():
+	# Falls back to the blocks built into the game if the file can't be read,
+	# e.g. in an exported build where res:// is packed.
+	var chunkLoader = get_node("../chunkLoader");
+	chunkLoader.load_blocks([blocks_path]);
 	var blocks_file = File.new();
-	blocks_file.open("res://assets/blocks.json", File.READ);
-	blocks_raw = parse_json(blocks_file.get_as_text())["blocks"];
-	populate_id_table()
+	blocks_file.open(blocks_path, File.READ);
+	var blocks_json = parse_json(blocks_file.get_as_text());
+	blocks_raw = blocks_json["blocks"];
+	module = blocks_json["module"];
+	populate_id_table(chunkLoader)
 
 func get_block(block_name: String):
 	var block_data = BlockData.new();
 	var raw_block = blocks_raw[block_name]
-	block_data.id = raw_block["id"]
+	block_data.id = names_to_ids[block_name]
 	block_data.name = raw_block["name"]
 	block_data.durability = raw_block["durability"];
 	return block_data
@@ -20,6 +30,9 @@ func get_block(block_name: String):
 func get_block_by_id(id: int):
 	return get_block(ids_to_names[id])
 
-func populate_id_table():
+# Block IDs are assigned when the blocks are loaded, so they come from the chunk loader.
+func populate_id_table(chunkLoader):
 	for raw_block in blocks_raw:
-		ids_to_names[int(blocks_raw[raw_block]["id"])] = raw_block
+		var id = chunkLoader.block_id(module + ":" + raw_block);
+		names_to_ids[raw_block] = id
+		ids_to_names[id] = raw_block
@@ -109,5 +109,11 @@ remote func PlayerInfo(networkID:int, pos:Vector3, camRotation:Vector2):
 	obj.global_transform.origin = pos;
 	obj.camRotation = camRotation;
 
+remote func BlockIds(blockIds:String):
+	Persistent.chunkLoader.set_server_block_ids(blockIds);
+
 remote func ChunkData(chunkData:PoolByteArray, chunkPos:Vector2):
 	Persistent.chunkLoader.receive_chunk(chunkData, chunkPos);
+
+remote func ChunksData(chunksData:Array):
+	Persistent.chunkLoader.receive_chunks(chunksData);
@@ -117,10 +117,9 @@ func UpdateMiscInfo(delta):
 func HandleActions(delta):
 	var network = Persistent.get_node("controllerNetwork");
 	if (Input.is_action_pressed("playerPrimaryAction")):
-		network.rpc_unreliable_id(1, "SetBlock", lookingAtBlock, 0);
+		network.rpc_unreliable_id(1, "SetBlock", lookingAtBlock, "basegame:air");
 	if (Input.is_action_pressed("playerSecondaryAction")):
-		network.rpc_unreliable_id(1, "SetBlock", adjacentLookingAtBlock, 23);
-		pass #Persistent.get_node("chunkGeneration").set_block_gd(adjacentLookingAtBlock, 23);
+		network.rpc_unreliable_id(1, "SetBlock", adjacentLookingAtBlock, "basegame:chestnut_wood");
 
 func HandleMovement(delta):
 	prevPos = global_transform.origin;
@@ -5,4 +5,5 @@ func _ready():
 
 func GenerateChunkMesh(pos:Vector2):
 	print("Entered ", pos);
+	Persistent.chunkLoader.set_center_chunk(pos);
 	Persistent.get_node("controllerNetwork").rpc_id(1, "SendChunkDataAround", pos);
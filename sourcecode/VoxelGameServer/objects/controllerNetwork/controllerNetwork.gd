@@ -19,6 +19,8 @@ var objPlayer = preload("res://objects/player/player.tscn");
 onready var chunkLoader = get_tree().get_root().get_node("world/chunkCreator")
 # macros!
 const gameinfoPlayerCredsPath = "res://gameinfo/player_creds.json";
+const gameinfoBlockModulesPath = "res://gameinfo/block_modules.json";
+const gameinfoWorldPath = "res://gameinfo/world";
 
 func _ready():
 	# load any info before starting (creds, world, etc)
@@ -33,6 +35,32 @@ func _ready():
 
 func PrepareGameInfo():
 	LoadPlayerCredentials();
+	LoadBlocks();
+	if (!chunkLoader.open_world_gd(gameinfoWorldPath)):
+		print("Failed to open the world, it won't be saved.")
+
+# loads the blocks.json of every module listed in the config, on top of the built-in blocks
+# falls back to the built-in blocks alone if the modules can't be read
+func LoadBlocks():
+	var modulePaths = [];
+	var file = File.new();
+	if (file.file_exists(gameinfoBlockModulesPath)):
+		file.open(gameinfoBlockModulesPath, File.READ);
+		var config = parse_json(file.get_as_text());
+		file.close();
+		if (typeof(config) == TYPE_DICTIONARY && typeof(config.get("modules")) == TYPE_ARRAY):
+			modulePaths = config["modules"];
+		else:
+			print("Invalid %s, using the built-in blocks." % gameinfoBlockModulesPath)
+	var changedChunks = chunkLoader.load_blocks_gd(modulePaths);
+	if (changedChunks == null):
+		print("Failed to load blocks, using the built-in ones.")
+		return;
+	SendBlocksChanged(changedChunks);
+
+func _notification(what):
+	if (what == NOTIFICATION_WM_QUIT_REQUEST):
+		chunkLoader.save_world_gd();
 
 func PrepareSpawn():
 	chunkLoader.load_around_chunk_gd(Vector2(0, 0));
@@ -62,6 +90,8 @@ func HasTicked() -> bool:
 func ClientConnected(id:int):
 	print_debug("DEBUG: Client %s connected." % id);
 	rpc_id(id, "ServerID", id);
+	# chunks are sent with the server's block ids, which the client translates into its own
+	rpc_id(id, "BlockIds", chunkLoader.block_ids_gd());
 	SendChunkDataAround(Vector2(0, 0))
 
 func ClientDisconnected(id:int):
@@ -144,13 +174,28 @@ remote func SendChunkDataAround(chunkPos: Vector2):
 	for chunkPos in positions:
 		SendChunkData(senderID, chunkPos)
 
-remote func SetBlock(blockPos:Vector3, blockID:int):
+func EncodeChunks(chunkPositions:Array) -> Array:
+	var chunksData = []
+	for chunkPos in chunkPositions:
+		var chunkData = chunkLoader.chunk_data_encoded(chunkPos);
+		if chunkData != null:
+			chunksData.append(chunkData)
+	return chunksData
+
+# blocks are sent by name, since clients may number them differently
+remote func SetBlock(blockPos:Vector3, blockName:String):
 	var senderID = get_tree().get_rpc_sender_id();
 	if (playerInstances.has(senderID) && is_instance_valid(playerInstances[senderID])):
 		var obj:Spatial = playerInstances[senderID];
-		if (obj.global_transform.origin.distance_to(blockPos) <= 4.0):
-			var chunkCreator = get_tree().get_root().get_node("world/chunkCreator")
-			print(get_tree().get_root())
-			chunkCreator.set_block_gd(blockPos, blockID);
-			var chunkPos:Vector2 = Vector2(floor(blockPos.x / 32), floor(blockPos.z / 32));
-			SendChunkData(senderID, chunkPos)
+		var blockID = chunkLoader.block_id_gd(blockName);
+		if (blockID != null && obj.global_transform.origin.distance_to(blockPos) <= 4.0):
+			var changedChunks = chunkLoader.set_block_gd(blockPos, blockID);
+			# send every changed chunk at once so the client relights them all before remeshing
+			rpc_id(senderID, "ChunksData", EncodeChunks(changedChunks));
+
+# the block ids changed, so every client needs the new ones and the renumbered chunks
+func SendBlocksChanged(changedChunks:Array):
+	if (get_tree().network_peer == null || changedChunks.empty()):
+		return;
+	rpc("BlockIds", chunkLoader.block_ids_gd());
+	rpc("ChunksData", EncodeChunks(changedChunks));
@@ -1,18 +1,27 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex, RwLock},
+    time::Instant,
 };
 
 use chunkcommon::{
+    block::{self, BlockIdTable, BlockRemap, BLOCK_MANAGER},
     chunk::ChunkData,
     chunkmesh::{
+        collision::ChunkCollisionData,
         nodes::{ChunkCollisionShape, ChunkMeshInstance, ChunkNode},
-        ChunkMeshData,
+        visibility::{visible_sections, SectionVisibility},
+        ChunkMeshData, Lod, MeshingMode,
     },
+    colormap,
+    constants::SECTIONS_PER_CHUNK,
     network::decode_compressed,
+    performance::Timings,
+    pool::{Job, WorkerPool},
     prelude::*,
+    vec3,
 };
-use gdnative::prelude::*;
+use gdnative::{api::ProjectSettings, prelude::*};
 
 pub struct ClientChunk {
     /// The chunk's position. This is determined by `Chunk.data.position` and
@@ -23,24 +32,131 @@ pub struct ClientChunk {
     pub data: Arc<RwLock<ChunkData>>,
     /// The chunk node, its manifestation in the Godot world.
     node: Arc<Mutex<ChunkNode>>,
+    /// How detailed the chunk's mesh is.
+    lod: Lod,
+    /// Which faces of each section can see each other, as of their latest meshes.
+    ///
+    /// Sections start out fully see-through until they've been meshed.
+    visibility: Arc<Mutex<[SectionVisibility; SECTIONS_PER_CHUNK]>>,
 }
 
 impl ClientChunk {
-    fn new(data: ChunkData, node: ChunkNode) -> Self {
+    fn new(data: ChunkData, node: ChunkNode, lod: Lod) -> Self {
         Self {
             position: data.position,
             data: Arc::new(RwLock::new(data)),
             node: Arc::new(Mutex::new(node)),
+            lod,
+            visibility: Arc::new(Mutex::new([SectionVisibility::ALL; SECTIONS_PER_CHUNK])),
         }
     }
 }
 
+/// Rebuilds the mesh of one section of a chunk, and optionally its collision boxes.
+struct MeshJob {
+    chunk_data: Arc<RwLock<ChunkData>>,
+    /// The loaded chunks, for checking the faces at the chunk's edges.
+    view: HashMap<ChunkPos, Arc<RwLock<ChunkData>>>,
+    node: Arc<Mutex<ChunkNode>>,
+    visibility: Arc<Mutex<[SectionVisibility; SECTIONS_PER_CHUNK]>>,
+    timings: Arc<Mutex<Timings>>,
+    section: usize,
+    mode: MeshingMode,
+    lod: Lod,
+    with_collision: bool,
+}
+
+impl Job for MeshJob {
+    type Output = (ChunkMeshData, Option<ChunkCollisionData>);
+    /// Whether the running job was rebuilding collision boxes.
+    type Handover = bool;
+
+    fn run(&self) -> Self::Output {
+        let collision = self.with_collision.then(|| {
+            let start = Instant::now();
+            let collision = ChunkCollisionData::new_from_section(
+                &self.chunk_data.read().unwrap(),
+                self.section,
+            );
+            self.timings
+                .lock()
+                .unwrap()
+                .build_collision
+                .push(start.elapsed());
+            collision
+        });
+        let start = Instant::now();
+        let (_, mesh_data) = ChunkMeshData::new_from_sections_threaded(
+            self.chunk_data.clone(),
+            self.view.clone(),
+            &[self.section],
+            self.mode,
+            self.lod,
+        )
+        .pop()
+        .unwrap();
+        let mut timings = self.timings.lock().unwrap();
+        timings.build_mesh.push(start.elapsed());
+        timings.mesh_sizes.push(mesh_data.uploaded_size());
+        drop(timings);
+        (mesh_data, collision)
+    }
+
+    fn apply(self, (mesh_data, collision): Self::Output) {
+        self.visibility.lock().unwrap()[self.section] = mesh_data.visibility();
+        let mut node = self.node.lock().unwrap();
+        node.update_mesh(self.section, &mesh_data);
+        if let Some(collision_data) = collision {
+            node.update_collision(self.section, &collision_data);
+        }
+    }
+
+    fn merge(&mut self, newer: Self) {
+        // The newer job has the latest level of detail and loaded chunks, but
+        // the collision still needs rebuilding if either of them asked for it.
+        let with_collision = self.with_collision || newer.with_collision;
+        *self = newer;
+        self.with_collision = with_collision;
+    }
+
+    fn handover(&self) -> bool {
+        self.with_collision
+    }
+
+    fn take_over(&mut self, with_collision: &bool) {
+        // The running job's collision boxes are thrown away along with its mesh
+        // once this one starts, so they have to be rebuilt here instead.
+        self.with_collision |= *with_collision;
+    }
+}
+
 #[derive(NativeClass)]
 #[export]
 #[inherit(Node)]
 pub struct ClientChunkLoader {
     base: Ref<Node, Shared>,
     chunks: HashMap<ChunkPos, ClientChunk>,
+    timings: Arc<Mutex<Timings>>,
+    /// Builds chunk meshes in the background, one section at a time,
+    /// closest to `center` first.
+    mesh_pool: WorkerPool<SectionPos, MeshJob>,
+    /// Whether to merge adjacent block faces when building chunk meshes,
+    /// rather than giving each face its own quad.
+    #[property(default = true)]
+    greedy_meshing: bool,
+    /// How many chunks away from `center` each level of detail starts.
+    ///
+    /// Chunks closer than this are drawn in full, and each time this distance
+    /// is passed again they're drawn at half the detail.
+    #[property(default = 3)]
+    lod_distance: u32,
+    /// The chunk the camera is in.
+    center: ChunkPos,
+    /// The numeric ID of every block on the server, which the chunks it sends use.
+    /// Until the server sends them, they're assumed to match this client's.
+    server_ids: Option<BlockIdTable>,
+    /// Turns the server's numeric IDs into this client's, see `server_ids`.
+    from_server: Option<BlockRemap>,
 }
 
 #[methods]
@@ -49,9 +165,28 @@ impl ClientChunkLoader {
         Self {
             base: unsafe { owner.assume_shared() },
             chunks: HashMap::new(),
+            timings: Arc::new(Mutex::new(Timings::new())),
+            mesh_pool: WorkerPool::with_available_threads(),
+            greedy_meshing: true,
+            lod_distance: 3,
+            center: ChunkPos::new(0, 0),
+            server_ids: None,
+            from_server: None,
         }
     }
 
+    /// Returns how many chunks away from `center` the chunk at `position` is.
+    fn distance_to(&self, position: ChunkPos) -> usize {
+        (position.x - self.center.x)
+            .abs()
+            .max((position.z - self.center.z).abs()) as usize
+    }
+
+    /// Returns the level of detail a chunk at `position` should be drawn at.
+    fn lod_at(&self, position: ChunkPos) -> Lod {
+        Lod::for_distance(self.distance_to(position), self.lod_distance as usize)
+    }
+
     /// Returns a "view" into `ClientChunkLoader.chunks`,
     /// mapping `ChunkPos`s to `RwLock`ed `ChunkData`s.
     fn data_view(&self) -> HashMap<ChunkPos, Arc<RwLock<ChunkData>>> {
@@ -61,25 +196,54 @@ impl ClientChunkLoader {
             .collect()
     }
 
-    fn update_mesh(&self, chunk: &ClientChunk) {
-        println!("Updating mesh data for {:?}", chunk.position);
+    /// Queues the meshes of `sections` of `chunk` to be rebuilt by the mesh pool,
+    /// along with their collision boxes if `with_collision` is `true`.
+    ///
+    /// Collision only depends on the blocks in the chunk, so it can be skipped when
+    /// e.g. only the chunk's level of detail has changed.
+    fn update_mesh(&self, chunk: &ClientChunk, sections: Vec<usize>, with_collision: bool) {
+        println!(
+            "Updating mesh data for {} sections of {:?}",
+            sections.len(),
+            chunk.position
+        );
         let view = self.data_view();
-        let chunk_node = chunk.node.clone();
-        let chunk_data = chunk.data.clone();
-        // TODO: Somehow use a single thread that gets sent chunks to build meshes for,
-        //       rather than creating a new one for each individually.
-        std::thread::spawn(move || {
-            let mesh_data = ChunkMeshData::new_from_chunk_data_threaded(chunk_data, view);
-            let mut chunk_node = chunk_node.lock().unwrap();
-            chunk_node.update(&mesh_data);
-        });
+        let mode = if self.greedy_meshing {
+            MeshingMode::Greedy
+        } else {
+            MeshingMode::Naive
+        };
+        for section in sections {
+            let job = MeshJob {
+                chunk_data: chunk.data.clone(),
+                view: view.clone(),
+                node: chunk.node.clone(),
+                visibility: chunk.visibility.clone(),
+                timings: self.timings.clone(),
+                section,
+                mode,
+                lod: chunk.lod,
+                with_collision,
+            };
+            self.mesh_pool.submit(
+                SectionPos::new(chunk.position, section),
+                self.distance_to(chunk.position),
+                job,
+            );
+        }
     }
 
-    fn update_nearby_meshes(&self, position: ChunkPos) {
-        let nearby = position.adjacent();
-        for nearby_position in nearby {
-            if let Some(chunk) = self.chunks.get(&nearby_position) {
-                self.update_mesh(chunk);
+    /// Rebuilds the meshes of `sections`, which may belong to several chunks,
+    /// skipping any that aren't loaded. See `update_mesh` for `with_collision`.
+    fn update_sections(&self, sections: HashSet<SectionPos>, with_collision: bool) {
+        let mut by_chunk: HashMap<ChunkPos, Vec<usize>> = HashMap::new();
+        for section in sections {
+            by_chunk.entry(section.chunk).or_default().push(section.y);
+        }
+        for (position, mut sections) in by_chunk {
+            if let Some(chunk) = self.chunks.get(&position) {
+                sections.sort_unstable();
+                self.update_mesh(chunk, sections, with_collision);
             }
         }
     }
@@ -88,9 +252,71 @@ impl ClientChunkLoader {
         println!("Spawning chunk {:?}", data.position);
         let mut node = ChunkNode::new_with_mesh();
         node.spawn(&*unsafe { self.base.assume_safe() }, data.position);
-        let chunk = ClientChunk::new(data, node);
-        self.update_mesh(&chunk);
-        self.chunks.insert(chunk.position, chunk);
+        let lod = self.lod_at(data.position);
+        let chunk = ClientChunk::new(data, node, lod);
+        let position = chunk.position;
+        self.update_mesh(&chunk, (0..SECTIONS_PER_CHUNK).collect(), true);
+        self.chunks.insert(position, chunk);
+        self.update_neighbours_of(position);
+    }
+
+    /// Rebuilds the meshes of the chunks next to the newly loaded chunk at `position`.
+    ///
+    /// Those chunks were meshed while it was missing, so they drew every face along
+    /// their shared edge. Only the sections with blocks on that edge are rebuilt,
+    /// unless the neighbour is less detailed, since its cells reach further in.
+    fn update_neighbours_of(&self, position: ChunkPos) {
+        for neighbour in position.adjacent() {
+            let chunk = match self.chunks.get(&neighbour) {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+            let sections = if chunk.lod == Lod::Full {
+                chunk.data.read().unwrap().sections_bordering(position)
+            } else {
+                (0..SECTIONS_PER_CHUNK).collect()
+            };
+            if !sections.is_empty() {
+                // Collision doesn't depend on the chunks around it.
+                self.update_mesh(chunk, sections, false);
+            }
+        }
+    }
+
+    /// Stores `received_chunk_data` in its loaded chunk, returning the blocks that
+    /// changed, or spawns the chunk if it wasn't loaded yet.
+    ///
+    /// Its blocks are first switched over from the server's IDs to this client's.
+    fn apply_chunk(&mut self, mut received_chunk_data: ChunkData) -> Vec<LocalBlockPos> {
+        if let Some(remap) = &self.from_server {
+            received_chunk_data.remap_blocks(remap);
+        }
+        if let Some(loaded_chunk) = self.chunks.get(&received_chunk_data.position) {
+            let mut chunk_data_write = loaded_chunk.data.write().unwrap();
+            let changed = chunk_data_write.changed_blocks(&received_chunk_data);
+            *chunk_data_write = received_chunk_data;
+            changed
+        } else {
+            self.spawn_chunk(received_chunk_data);
+            Vec::new()
+        }
+    }
+
+    /// Rebuilds the sections around the `changed` blocks of loaded chunks.
+    ///
+    /// Only the sections around blocks that actually changed need new meshes, which
+    /// may include sections of the chunks next to them if the blocks are on their
+    /// `borders`. Only the changed blocks' own sections need new collision boxes,
+    /// though.
+    fn update_changed(&self, changed: &[LocalBlockPos]) {
+        self.update_sections(
+            changed
+                .iter()
+                .flat_map(LocalBlockPos::affected_sections)
+                .collect(),
+            false,
+        );
+        self.update_sections(changed.iter().map(LocalBlockPos::section).collect(), true);
     }
 
     #[export]
@@ -98,13 +324,203 @@ impl ClientChunkLoader {
         let position = ChunkPos::new(position.x as isize, position.y as isize);
         let data = data.read();
         let received_chunk_data: ChunkData = decode_compressed(&*data);
-        if let Some(loaded_chunk) = self.chunks.get(&position) {
-            let mut chunk_data_write = loaded_chunk.data.write().unwrap();
-            *chunk_data_write = received_chunk_data;
-            self.update_mesh(loaded_chunk);
-        } else {
-            self.spawn_chunk(received_chunk_data);
+        debug_assert_eq!(received_chunk_data.position, position);
+        let changed = self.apply_chunk(received_chunk_data);
+        self.update_changed(&changed);
+    }
+
+    #[export]
+    /// Receives every chunk touched by a single block edit on the server.
+    ///
+    /// Changing a block can change the light of the chunks around it, so all of them
+    /// are stored before any mesh is queued. Otherwise the sections on the edited
+    /// chunk's borders would be meshed with their neighbours' old light.
+    fn receive_chunks(&mut self, _base: &Node, data: Vec<ByteArray>) {
+        let mut changed = Vec::new();
+        for data in data {
+            let data = data.read();
+            changed.extend(self.apply_chunk(decode_compressed(&*data)));
         }
+        self.update_changed(&changed);
+    }
+
+    #[export]
+    /// Moves the camera to the chunk at `position`, rebuilding the meshes of any chunks
+    /// whose level of detail changes because of it.
+    ///
+    /// Meshes that are still waiting to be built are reordered to match.
+    fn set_center_chunk(&mut self, _base: &Node, position: Vector2) {
+        self.center = ChunkPos::new(position.x as isize, position.y as isize);
+        self.mesh_pool
+            .reprioritize(|section| self.distance_to(section.chunk));
+        let changed: Vec<ChunkPos> = self
+            .chunks
+            .iter()
+            .filter(|(position, chunk)| chunk.lod != self.lod_at(**position))
+            .map(|(position, _)| *position)
+            .collect();
+        for position in changed {
+            let lod = self.lod_at(position);
+            self.chunks.get_mut(&position).unwrap().lod = lod;
+            self.update_mesh(
+                &self.chunks[&position],
+                (0..SECTIONS_PER_CHUNK).collect(),
+                false,
+            );
+        }
+    }
+
+    /// Returns the sections that can be seen from `camera` while looking along
+    /// `direction`, see [`visible_sections`].
+    ///
+    /// Every loaded section is returned if the camera isn't in one of them.
+    fn find_visible_sections(&self, camera: Vector3, direction: Vector3) -> HashSet<SectionPos> {
+        let visible = visible_sections(
+            [camera.x, camera.y, camera.z],
+            [direction.x, direction.y, direction.z],
+            |section| {
+                let chunk = self.chunks.get(&section.chunk)?;
+                let visibility = chunk.visibility.lock().unwrap();
+                Some(visibility[section.y])
+            },
+        );
+        visible.unwrap_or_else(|| {
+            self.chunks
+                .keys()
+                .flat_map(|position| {
+                    (0..SECTIONS_PER_CHUNK).map(|section| SectionPos::new(*position, section))
+                })
+                .collect()
+        })
+    }
+
+    #[export]
+    /// Returns the sections that can be seen from `camera` while looking along
+    /// `direction`, as `(chunk x, section, chunk z)`.
+    fn visible_sections(&self, _base: &Node, camera: Vector3, direction: Vector3) -> Vec<Vector3> {
+        self.find_visible_sections(camera, direction)
+            .into_iter()
+            .map(|section| vec3!(section.chunk.x, section.y, section.chunk.z))
+            .collect()
+    }
+
+    #[export]
+    /// Hides the meshes of every section that can't be seen from `camera` while
+    /// looking along `direction`, and shows the rest.
+    fn cull_sections(&self, _base: &Node, camera: Vector3, direction: Vector3) {
+        let visible = self.find_visible_sections(camera, direction);
+        for (position, chunk) in &self.chunks {
+            let mut node = chunk.node.lock().unwrap();
+            for section in 0..SECTIONS_PER_CHUNK {
+                let is_visible = visible.contains(&SectionPos::new(*position, section));
+                node.set_section_visible(section, is_visible);
+            }
+        }
+    }
+
+    #[export]
+    /// Changes the season that tinted blocks are coloured for, from 0 (spring) to
+    /// 3 (winter), and rebuilds the meshes of every loaded chunk to match.
+    ///
+    /// See [`colormap::set_season`].
+    fn set_season(&mut self, _base: &Node, season: f32) {
+        colormap::set_season(season);
+        for chunk in self.chunks.values() {
+            self.update_mesh(chunk, (0..SECTIONS_PER_CHUNK).collect(), false);
+        }
+    }
+
+    #[export]
+    /// Receives the numeric ID of every block on the server, as JSON, so that the
+    /// chunks it sends from now on can be translated into this client's IDs.
+    ///
+    /// Returns `true` on success.
+    fn set_server_block_ids(&mut self, _base: &Node, json: String) -> bool {
+        let table = match BlockIdTable::from_json(json.as_bytes()) {
+            Ok(table) => table,
+            Err(error) => {
+                godot_warn!("Couldn't read the server's block IDs: {}", error);
+                return false;
+            }
+        };
+        self.server_ids = Some(table);
+        self.update_server_remap();
+        true
+    }
+
+    /// Works out how to translate the server's block IDs into the current ones.
+    fn update_server_remap(&mut self) {
+        self.from_server = self.server_ids.as_ref().map(|table| {
+            let remap = BLOCK_MANAGER.get().remap_from(table);
+            for name in &remap.missing {
+                godot_warn!("The server's {} doesn't exist here, showing air", name);
+            }
+            remap
+        });
+    }
+
+    /// Switches the loaded chunks over to the current block definitions, after they've
+    /// changed from `old_ids`, and rebuilds their meshes and collision boxes.
+    fn blocks_changed(&mut self, old_ids: &BlockIdTable) {
+        self.update_server_remap();
+        let remap = BLOCK_MANAGER.get().remap_from(old_ids);
+        for chunk in self.chunks.values() {
+            chunk.data.write().unwrap().remap_blocks(&remap);
+            self.update_mesh(chunk, (0..SECTIONS_PER_CHUNK).collect(), true);
+        }
+    }
+
+    #[export]
+    /// Loads the block definitions from the `blocks.json` file of each module at
+    /// `paths`, which may be Godot paths such as `res://`, instead of the ones built
+    /// into the game. The built-in `basegame` module is kept unless one of them
+    /// replaces it.
+    ///
+    /// Blocks the server has that aren't in any of them are shown as air. Returns
+    /// `true` on success, otherwise the current definitions are kept.
+    fn load_blocks(&mut self, _base: &Node, paths: Vec<GodotString>) -> bool {
+        let settings = ProjectSettings::godot_singleton();
+        let paths: Vec<String> = paths
+            .into_iter()
+            .map(|path| settings.globalize_path(path).to_string())
+            .collect();
+        let old_ids = BLOCK_MANAGER.get().id_table();
+        if let Err(error) = block::load_blocks(&paths) {
+            godot_warn!("Couldn't load blocks from {:?}: {}", paths, error);
+            return false;
+        }
+        self.blocks_changed(&old_ids);
+        true
+    }
+
+    #[export]
+    /// Loads the block definitions again from the file they were last loaded from,
+    /// and rebuilds every loaded chunk with them, e.g. after editing it during
+    /// development.
+    ///
+    /// Returns `true` on success, otherwise the current definitions are kept.
+    fn reload_blocks(&mut self, _base: &Node) -> bool {
+        let old_ids = BLOCK_MANAGER.get().id_table();
+        if let Err(error) = block::reload_blocks() {
+            godot_warn!("Couldn't reload blocks: {}", error);
+            return false;
+        }
+        self.blocks_changed(&old_ids);
+        true
+    }
+
+    #[export]
+    /// Returns the numeric ID of the block named `name`, like `basegame:grass`,
+    /// or `null` if there's no such block.
+    fn block_id(&self, _base: &Node, name: String) -> Option<BlockID> {
+        BLOCK_MANAGER.get().block(&name).map(|block| block.id)
+    }
+
+    #[export]
+    /// Prints the average time taken to build chunk meshes,
+    /// and how much GPU memory they take up.
+    fn print_timings(&self, _base: &Node) {
+        godot_print!("{}", self.timings.lock().unwrap());
     }
 
     fn _ready(&self, _owner: &Node) {
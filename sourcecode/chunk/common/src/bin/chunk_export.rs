@@ -0,0 +1,53 @@
+//! Exports chunks saved by the server (see `save_region_gd`) as an OBJ model.
+//!
+//! Usage: `chunk-export <output.obj> <texture directory> <chunk files...>`
+
+use std::{env, fs, path::Path, process};
+
+use chunkcommon::{
+    block::{BlockIdTable, BLOCK_IDS_FILE, BLOCK_MANAGER},
+    chunk::ChunkData,
+    export::export_obj,
+    network::try_decode_compressed,
+};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 3 {
+        eprintln!("Usage: chunk-export <output.obj> <texture directory> <chunk files...>");
+        process::exit(1);
+    }
+    let chunks = args[2..]
+        .iter()
+        .map(|file| {
+            let mut chunk: ChunkData =
+                match fs::read(file).and_then(|bytes| try_decode_compressed(&bytes)) {
+                    Ok(chunk) => chunk,
+                    Err(error) => {
+                        eprintln!("Couldn't read {}: {}", file, error);
+                        process::exit(1);
+                    }
+                };
+            // The blocks may have changed since the chunk was saved. Chunks saved
+            // without a table are assumed to use the current IDs.
+            let directory = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+            if let Ok(table) = BlockIdTable::load(directory) {
+                let remap = BLOCK_MANAGER.get().remap_from(&table);
+                for name in &remap.missing {
+                    eprintln!("{} in {} no longer exists, using air", name, file);
+                }
+                chunk.remap_blocks(&remap);
+            } else if directory.join(BLOCK_IDS_FILE).exists() {
+                eprintln!("Couldn't read the block IDs next to {}", file);
+                process::exit(1);
+            }
+            chunk
+        })
+        .collect::<Vec<_>>();
+    let chunks = chunks.iter().collect::<Vec<_>>();
+    if let Err(error) = export_obj(&chunks, Path::new(&args[0]), &args[1]) {
+        eprintln!("Couldn't export to {}: {}", args[0], error);
+        process::exit(1);
+    }
+    println!("Exported {} chunks to {}", chunks.len(), args[0]);
+}
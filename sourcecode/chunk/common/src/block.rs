@@ -1,24 +1,242 @@
 use lazy_static::lazy_static;
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
+use crate::{
+    colormap::{self, BlockTint, Colormap, ColormapManager, COLORMAPS_FILE, COLORMAP_MANAGER},
+    errors::BlockLoadError,
+    model::{self, ModelDefinition, ModelManager, MODELS_FILE, MODEL_MANAGER},
+    reloadable::Reloadable,
+    texture::{BlockFace, BlockTextures, TEXTURE_REGISTRY},
+};
+
+/// The block definitions used until others are loaded with [`load_blocks`].
 pub(crate) const BLOCKS_JSON: &str = include_str!("../../../VoxelGame/assets/blocks.json"); // HARDCODED
 pub type BlockID = u16;
 
+/// Which pass the faces of a block are drawn in.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderLayer {
+    /// Fully opaque textures.
+    Opaque,
+    /// Textures whose pixels are either fully opaque or fully transparent, like leaves.
+    Cutout,
+    /// Partially see-through textures, like glass or water, which are blended with
+    /// whatever is behind them.
+    ///
+    /// Each section is its own mesh, which Godot draws back to front along with
+    /// other transparent meshes, but the faces within a section aren't sorted.
+    /// Overlapping translucent faces in the same section may blend in the wrong
+    /// order, though culling faces between blocks of the same kind hides most of them.
+    Translucent,
+}
+
+impl RenderLayer {
+    pub const ALL: [RenderLayer; 3] = [
+        RenderLayer::Opaque,
+        RenderLayer::Cutout,
+        RenderLayer::Translucent,
+    ];
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Block {
+    /// The block's numeric ID, which is what chunks store. These are assigned when
+    /// the blocks are loaded rather than in `blocks.json`, see [`BlockManager`].
+    #[serde(skip)]
     pub id: BlockID,
+    /// The block's namespaced ID, like `basegame:grass`: the module it's from,
+    /// and its key in that module's `blocks.json`.
+    #[serde(skip)]
+    pub key: String,
     pub name: String,
     #[serde(default)]
     pub transparent: bool,
     pub durability: f64,
+    /// How much light this block gives off, from 0 to 15.
+    #[serde(default)]
+    pub light_emission: u8,
+    /// How much extra light is lost when passing through this block.
+    ///
+    /// Only used for transparent blocks, opaque ones block light entirely.
+    pub light_filter: Option<u8>,
+    /// The textures used by each face of this block.
+    ///
+    /// Blocks without any use the texture strip named after their key.
+    pub textures: Option<BlockTextures>,
+    /// The name of the model in `block_models.json` this block uses,
+    /// or `None` if it's a plain cube.
+    pub model: Option<String>,
+    /// The pass this block is drawn in.
+    ///
+    /// Defaults to `Cutout` for transparent blocks, and `Opaque` for everything else.
+    render_layer: Option<RenderLayer>,
+    /// Whether faces between two of this block are hidden, like the insides of
+    /// a leaf canopy or a wall of glass.
+    ///
+    /// Only matters for transparent blocks, since faces between opaque blocks are
+    /// always hidden.
+    #[serde(default)]
+    pub cull_same_block: bool,
+    /// Whether things collide with this block.
+    ///
+    /// Defaults to `true`. Blocks with models collide with their model's
+    /// collision boxes rather than a whole cube.
+    solid: Option<bool>,
+    /// The colour map that tints this block's faces, like grass changing colour
+    /// from one climate to the next.
+    pub tint: Option<BlockTint>,
     // FUTURE: This will house fields such as toughness,
     //         tool preference, etc.
 }
 
-/// Loads and provides access to block data from the `blocks.json` file.
-pub struct BlockManager {
+impl Block {
+    /// Returns the block's key within its module, like `grass` for `basegame:grass`.
+    pub fn local_name(&self) -> &str {
+        self.key.split_once(':').map_or(&self.key, |(_, name)| name)
+    }
+
+    /// Returns `true` if this block can't be broken, i.e. its durability is negative.
+    pub fn unbreakable(&self) -> bool {
+        self.durability < 0.0
+    }
+
+    /// Returns the pass this block is drawn in.
+    pub fn render_layer(&self) -> RenderLayer {
+        match self.render_layer {
+            Some(layer) => layer,
+            None if self.transparent => RenderLayer::Cutout,
+            None => RenderLayer::Opaque,
+        }
+    }
+
+    /// Returns `true` if things collide with this block.
+    pub fn solid(&self) -> bool {
+        self.solid.unwrap_or(true)
+    }
+}
+
+/// The module that block names without one, like `"grass"`, belong to.
+pub const DEFAULT_MODULE: &str = "basegame";
+
+/// The file next to a world's saved chunks that holds its [`BlockIdTable`].
+pub const BLOCK_IDS_FILE: &str = "block_ids.json";
+
+/// The files of a single module: its `blocks.json`, along with the [`MODELS_FILE`]
+/// and [`COLORMAPS_FILE`] next to it, if it has models or colour maps of its own.
+#[derive(Clone, Copy)]
+pub struct ModuleFiles<'a> {
+    pub blocks: &'a [u8],
+    pub models: Option<&'a [u8]>,
+    pub colormaps: Option<&'a [u8]>,
+}
+
+impl ModuleFiles<'static> {
+    /// The [`DEFAULT_MODULE`] built into the game.
+    pub const EMBEDDED: Self = Self {
+        blocks: BLOCKS_JSON.as_bytes(),
+        models: Some(model::MODELS_JSON.as_bytes()),
+        colormaps: Some(colormap::COLORMAPS_JSON.as_bytes()),
+    };
+}
+
+/// The contents of a module's files, read from disk by [`ModuleContents::read`].
+struct ModuleContents {
+    blocks: Vec<u8>,
+    models: Option<Vec<u8>>,
+    colormaps: Option<Vec<u8>>,
+}
+
+impl ModuleContents {
+    /// Reads the module whose `blocks.json` is at `path`, along with the files
+    /// next to it.
+    fn read(path: &Path) -> Result<Self, BlockLoadError> {
+        let read_sibling = |name| match fs::read(path.with_file_name(name)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        };
+        Ok(Self {
+            blocks: fs::read(path)?,
+            models: read_sibling(MODELS_FILE)?,
+            colormaps: read_sibling(COLORMAPS_FILE)?,
+        })
+    }
+
+    fn files(&self) -> ModuleFiles<'_> {
+        ModuleFiles {
+            blocks: &self.blocks,
+            models: self.models.as_deref(),
+            colormaps: self.colormaps.as_deref(),
+        }
+    }
+}
+
+/// The models and colour maps of every module, by name. Blocks can use those of
+/// any module, not just their own.
+#[derive(Default)]
+struct BlockAssets {
+    models: HashMap<String, ModelDefinition>,
+    colormaps: HashMap<String, Colormap>,
+}
+
+impl BlockAssets {
+    /// Parses the models and colour maps of `module`, and adds them to the others.
+    fn add(&mut self, module: &ModuleFiles) -> Result<(), BlockLoadError> {
+        if let Some(bytes) = module.models {
+            Self::merge(&mut self.models, model::parse_models(bytes)?)?;
+        }
+        if let Some(bytes) = module.colormaps {
+            Self::merge(&mut self.colormaps, colormap::parse_colormaps(bytes)?)?;
+        }
+        Ok(())
+    }
+
+    fn merge<T>(
+        assets: &mut HashMap<String, T>,
+        new: HashMap<String, T>,
+    ) -> Result<(), BlockLoadError> {
+        for (name, asset) in new {
+            if assets.contains_key(&name) {
+                return Err(BlockLoadError::DuplicateAsset(name));
+            }
+            assets.insert(name, asset);
+        }
+        Ok(())
+    }
+}
+
+/// A `blocks.json` file, which holds the blocks of a single module.
+#[derive(Deserialize)]
+struct BlocksFile {
+    module: String,
     blocks: HashMap<String, Block>,
+}
+
+impl BlocksFile {
+    fn parse(bytes: &[u8]) -> Result<Self, BlockLoadError> {
+        let file: Self = serde_json::from_slice(bytes)?;
+        let names = std::iter::once(&file.module).chain(file.blocks.keys());
+        for name in names {
+            if name.is_empty() || name.contains(':') {
+                return Err(BlockLoadError::InvalidName(name.clone()));
+            }
+        }
+        Ok(file)
+    }
+}
+
+/// Loads and provides access to block data from the `blocks.json` files of each module.
+pub struct BlockManager {
+    blocks: HashMap<BlockID, Block>,
+    /// Maps namespaced block IDs (like `"basegame:grass"`) to numeric ones.
+    names: HashMap<String, BlockID>,
     // TODO: `transparent_blocks` shouldn't be necessary. `ChunkData.terrain` should
     //       be updated to store a type like `Block`, however as it stands that's
     //       going to waste a lot of memory. We need to find a way to make something
@@ -27,31 +245,341 @@ pub struct BlockManager {
 }
 
 impl BlockManager {
+    /// Loads the block definitions embedded in the game.
     fn new() -> Self {
-        // TODO: Learn serde.
-        let mut game_dict: HashMap<String, serde_json::Value> =
-            serde_json::from_str(BLOCKS_JSON).unwrap();
-        let blocks: HashMap<String, Block> =
-            serde_json::from_value(game_dict.remove("blocks").unwrap()).unwrap();
+        Self::from_bytes(BLOCKS_JSON.as_bytes()).unwrap()
+    }
+
+    /// Parses the contents of a single module's `blocks.json` file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BlockLoadError> {
+        Self::from_modules(&[bytes])
+    }
+
+    /// Parses the contents of the `blocks.json` file of each module.
+    pub fn from_modules(modules: &[&[u8]]) -> Result<Self, BlockLoadError> {
+        let files = modules
+            .iter()
+            .map(|bytes| BlocksFile::parse(bytes))
+            .collect::<Result<_, _>>()?;
+        Self::from_files(files)
+    }
+
+    /// Gives every block a numeric ID.
+    ///
+    /// Air is always 0, since that's what empty space is filled with, and every
+    /// other block is numbered in order of its namespaced ID. That way, the same
+    /// modules always end up with the same IDs, so the server and its clients agree
+    /// on them, but they may change whenever a module is added or removed.
+    fn from_files(files: Vec<BlocksFile>) -> Result<Self, BlockLoadError> {
+        let mut modules = Vec::new();
+        let mut blocks = Vec::new();
+        for file in files {
+            if modules.contains(&file.module) {
+                return Err(BlockLoadError::DuplicateModule(file.module));
+            }
+            for (name, mut block) in file.blocks {
+                block.key = format!("{}:{}", file.module, name);
+                blocks.push(block);
+            }
+            modules.push(file.module);
+        }
+        blocks.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        let air = format!("{}:air", DEFAULT_MODULE);
+        let mut next_id: BlockID = 1;
+        for block in &mut blocks {
+            if block.key == air {
+                continue;
+            }
+            block.id = next_id;
+            next_id = next_id
+                .checked_add(1)
+                .ok_or(BlockLoadError::TooManyBlocks)?;
+        }
+
         let transparent_blocks = blocks
             .iter()
-            // TODO: Replace with `.then_some(block.id)` if `bool_to_option` gets stabilized
-            //       (https://github.com/rust-lang/rust/issues/80967)
-            .filter_map(|(_, block)| block.transparent.then(|| block.id))
+            .filter(|block| block.transparent)
+            .map(|block| block.id)
             .collect();
-        Self {
+        let names = blocks
+            .iter()
+            .map(|block| (block.key.clone(), block.id))
+            .collect();
+        let blocks = blocks.into_iter().map(|block| (block.id, block)).collect();
+        Ok(Self {
             blocks,
+            names,
             transparent_blocks,
+        })
+    }
+
+    /// Checks that every model and colour map the blocks use is in `assets`, and
+    /// that their textures can be used.
+    fn validate(&self, assets: &BlockAssets) -> Result<(), BlockLoadError> {
+        for block in self.blocks() {
+            for face in BlockFace::ALL {
+                let texture = match &block.textures {
+                    Some(textures) => textures.face(face),
+                    None => break,
+                };
+                texture
+                    .check()
+                    .map_err(|reason| BlockLoadError::InvalidTexture {
+                        block: block.key.clone(),
+                        texture: texture.texture.clone(),
+                        reason,
+                    })?;
+            }
+            let tint = block.tint.as_ref().map(|tint| &tint.colormap);
+            let missing = match (&block.model, tint) {
+                (Some(name), _) if !assets.models.contains_key(name) => name,
+                (_, Some(name)) if !assets.colormaps.contains_key(name) => name,
+                _ => continue,
+            };
+            return Err(BlockLoadError::MissingAsset {
+                block: block.key.clone(),
+                asset: missing.clone(),
+            });
         }
+        Ok(())
     }
 
+    /// Returns the block with the namespaced ID `block_name`, like `"basegame:grass"`.
+    ///
+    /// Names without a module, like `"grass"`, are looked up in [`DEFAULT_MODULE`].
     pub fn block(&self, block_name: &str) -> Option<&Block> {
-        self.blocks.get(block_name)
+        let id = if block_name.contains(':') {
+            self.names.get(block_name)
+        } else {
+            self.names
+                .get(&format!("{}:{}", DEFAULT_MODULE, block_name))
+        };
+        self.blocks.get(id?)
+    }
+
+    pub fn block_by_id(&self, block_id: BlockID) -> Option<&Block> {
+        self.blocks.get(&block_id)
+    }
+
+    /// Returns every block, in no particular order.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// Returns the numeric ID of every block, to be saved along with chunks
+    /// that use them.
+    pub fn id_table(&self) -> BlockIdTable {
+        BlockIdTable {
+            ids: self
+                .names
+                .iter()
+                .map(|(name, id)| (name.clone(), *id))
+                .collect(),
+        }
+    }
+
+    /// Returns how to turn the numeric IDs in `table` into the current ones.
+    ///
+    /// Blocks that no longer exist, e.g. because their module was removed, become air.
+    pub fn remap_from(&self, table: &BlockIdTable) -> BlockRemap {
+        let size = table.ids.values().max().map_or(0, |id| *id as usize + 1);
+        let mut ids = vec![0; size];
+        let mut missing = Vec::new();
+        for (name, old_id) in &table.ids {
+            match self.names.get(name) {
+                Some(new_id) => ids[*old_id as usize] = *new_id,
+                None => missing.push(name.clone()),
+            }
+        }
+        BlockRemap { ids, missing }
+    }
+
+    /// Returns how to turn the current numeric IDs into the ones in `table`, the
+    /// opposite of [`BlockManager::remap_from`].
+    ///
+    /// Blocks that aren't in `table` become air, see [`BlockIdTable::extend`].
+    pub fn remap_to(&self, table: &BlockIdTable) -> BlockRemap {
+        let size = self.names.values().max().map_or(0, |id| *id as usize + 1);
+        let mut ids = vec![0; size];
+        let mut missing = Vec::new();
+        for (name, current_id) in &self.names {
+            match table.ids.get(name) {
+                Some(id) => ids[*current_id as usize] = *id,
+                None => missing.push(name.clone()),
+            }
+        }
+        missing.sort_unstable();
+        BlockRemap { ids, missing }
+    }
+}
+
+/// The numeric ID of every block, by namespaced ID, when a world was saved.
+///
+/// Numeric IDs change whenever modules are added or removed, so each world keeps
+/// the table its chunks were saved with in [`BLOCK_IDS_FILE`], and remaps them
+/// with [`BlockManager::remap_from`] when they're loaded again.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockIdTable {
+    ids: BTreeMap<String, BlockID>,
+}
+
+impl BlockIdTable {
+    /// Parses a table written by [`BlockIdTable::to_json`].
+    pub fn from_json(bytes: &[u8]) -> Result<Self, BlockLoadError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Writes the table as JSON, e.g. to send it to clients.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Reads the table from the [`BLOCK_IDS_FILE`] in `directory`.
+    pub fn load(directory: impl AsRef<Path>) -> Result<Self, BlockLoadError> {
+        Self::from_json(&fs::read(directory.as_ref().join(BLOCK_IDS_FILE))?)
     }
+
+    /// Writes the table to the [`BLOCK_IDS_FILE`] in `directory`.
+    pub fn save(&self, directory: impl AsRef<Path>) -> std::io::Result<()> {
+        crate::world::write_atomically(directory.as_ref().join(BLOCK_IDS_FILE), self.to_json())
+    }
+
+    /// Adds every block in `blocks` that isn't in the table yet, giving each a numeric
+    /// ID the table doesn't use, so that chunks with those blocks can be saved with it.
+    ///
+    /// An empty table takes the current IDs as they are.
+    pub fn extend(&mut self, blocks: &BlockManager) -> Result<(), BlockLoadError> {
+        if self.ids.is_empty() {
+            *self = blocks.id_table();
+            return Ok(());
+        }
+        let mut next_id = self.ids.values().max().map_or(0, |id| *id as u32 + 1);
+        for name in blocks.id_table().ids.into_keys() {
+            if self.ids.contains_key(&name) {
+                continue;
+            }
+            let id = BlockID::try_from(next_id).map_err(|_| BlockLoadError::TooManyBlocks)?;
+            self.ids.insert(name, id);
+            next_id += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Turns the numeric IDs a chunk was saved with into the current ones,
+/// see [`BlockManager::remap_from`].
+pub struct BlockRemap {
+    /// The current ID of each old one.
+    ids: Vec<BlockID>,
+    /// The namespaced IDs of the blocks that no longer exist.
+    pub missing: Vec<String>,
 }
 
+impl BlockRemap {
+    /// Returns the current ID of the block that was saved as `block_id`,
+    /// or air if there's no such block.
+    pub fn get(&self, block_id: BlockID) -> BlockID {
+        self.ids.get(block_id as usize).copied().unwrap_or(0)
+    }
+
+    /// Returns `true` if none of the IDs have changed.
+    pub fn is_identity(&self) -> bool {
+        self.missing.is_empty()
+            && self
+                .ids
+                .iter()
+                .enumerate()
+                .all(|(old, new)| old == *new as usize)
+    }
+}
+
+/// The block definitions in use, which are the embedded ones until others are loaded.
+pub static BLOCK_MANAGER: Reloadable<BlockManager> = Reloadable::new(BlockManager::new);
+
 lazy_static! {
-    pub static ref BLOCK_MANAGER: BlockManager = BlockManager::new();
+    /// The files the block definitions were last loaded from, if any.
+    static ref BLOCKS_PATHS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+/// Everything built from the files of a set of modules, see [`build_definitions`].
+struct Definitions {
+    blocks: BlockManager,
+    models: ModelManager,
+    colormaps: ColormapManager,
+}
+
+/// Parses and checks the files of each module, once each.
+///
+/// The embedded [`DEFAULT_MODULE`] is included too, unless one of `modules` replaces it.
+fn build_definitions(modules: &[ModuleFiles]) -> Result<Definitions, BlockLoadError> {
+    let mut files = Vec::new();
+    let mut assets = BlockAssets::default();
+    for module in modules {
+        files.push(BlocksFile::parse(module.blocks)?);
+        assets.add(module)?;
+    }
+    if !files.iter().any(|file| file.module == DEFAULT_MODULE) {
+        files.push(BlocksFile::parse(ModuleFiles::EMBEDDED.blocks)?);
+        assets.add(&ModuleFiles::EMBEDDED)?;
+    }
+    let blocks = BlockManager::from_files(files)?;
+    blocks.validate(&assets)?;
+    Ok(Definitions {
+        models: ModelManager::from_definitions(&blocks, &assets.models),
+        colormaps: ColormapManager::from_definitions(&blocks, &assets.colormaps),
+        blocks,
+    })
+}
+
+/// Replaces the block definitions in use with those of `modules`, along with their
+/// models and colour maps, and throws away everything else built from the old ones,
+/// so it's rebuilt from the new ones when next used.
+///
+/// Anything already built from the old definitions, like chunk meshes, isn't
+/// updated, so this is best done before any chunks are loaded.
+fn install(modules: &[ModuleFiles]) -> Result<(), BlockLoadError> {
+    let definitions = build_definitions(modules)?;
+    BLOCK_MANAGER.set(definitions.blocks);
+    MODEL_MANAGER.set(definitions.models);
+    COLORMAP_MANAGER.set(definitions.colormaps);
+    TEXTURE_REGISTRY.reset();
+    #[cfg(feature = "godot")]
+    crate::chunkmesh::material::MATERIALS.reset();
+    Ok(())
+}
+
+/// Loads the block definitions from the `blocks.json` files of each module at `paths`,
+/// and the models and colour maps next to them, replacing the ones in use. They're
+/// left as they are if any file can't be loaded.
+pub fn load_blocks(paths: &[impl AsRef<Path>]) -> Result<(), BlockLoadError> {
+    let modules = paths
+        .iter()
+        .map(|path| ModuleContents::read(path.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    install(
+        &modules
+            .iter()
+            .map(ModuleContents::files)
+            .collect::<Vec<_>>(),
+    )?;
+    *BLOCKS_PATHS.lock().unwrap() = paths.iter().map(|path| path.as_ref().into()).collect();
+    Ok(())
+}
+
+/// Loads the block definitions from the contents of each module's files, replacing
+/// the ones in use. They're left as they are if any of them aren't valid.
+pub fn load_blocks_from_bytes(modules: &[ModuleFiles]) -> Result<(), BlockLoadError> {
+    install(modules)?;
+    BLOCKS_PATHS.lock().unwrap().clear();
+    Ok(())
+}
+
+/// Loads the block definitions again from the files passed to [`load_blocks`],
+/// or the embedded ones if they weren't loaded from files, e.g. after editing
+/// `blocks.json` during development.
+pub fn reload_blocks() -> Result<(), BlockLoadError> {
+    let paths = BLOCKS_PATHS.lock().unwrap().clone();
+    load_blocks(&paths)
 }
 
 #[cfg(test)]
@@ -60,15 +588,232 @@ mod tests {
 
     #[test]
     fn test_block_manager() {
-        let block = BLOCK_MANAGER.block("air").unwrap();
+        let blocks = BLOCK_MANAGER.get();
+        let block = blocks.block("air").unwrap();
         assert_eq!(block.id, 0);
-        assert_eq!(block.transparent, true);
-        let block = BLOCK_MANAGER.block("dirt").unwrap();
-        assert_eq!(block.id, 21);
-        assert_eq!(block.transparent, false);
-
-        assert!(BLOCK_MANAGER.transparent_blocks.contains(&24));
-        assert!(BLOCK_MANAGER.transparent_blocks.contains(&0));
-        assert!(!BLOCK_MANAGER.transparent_blocks.contains(&21));
+        assert!(block.transparent);
+        let block = blocks.block("dirt").unwrap();
+        assert_eq!(block.key, "basegame:dirt");
+        assert!(!block.transparent);
+        let dirt = block.id;
+
+        let silicate = blocks.block("silicate").unwrap().id;
+        let block = blocks.block_by_id(silicate).unwrap();
+        assert_eq!(block.name, "Silicate");
+        assert!(block.unbreakable());
+        assert!(!blocks.block("stone").unwrap().unbreakable());
+
+        let block = blocks.block("lantern").unwrap();
+        assert_eq!(block.light_emission, 15);
+        assert_eq!(block.light_filter, None);
+        assert_eq!(
+            blocks.block("chestnut_leaves").unwrap().light_filter,
+            Some(1)
+        );
+
+        let leaves = blocks.block("chestnut_leaves").unwrap();
+        assert_eq!(leaves.render_layer(), RenderLayer::Cutout);
+        assert!(leaves.cull_same_block);
+        let water = blocks.block("water").unwrap();
+        assert_eq!(water.render_layer(), RenderLayer::Translucent);
+        assert_eq!(
+            blocks.block("dirt").unwrap().render_layer(),
+            RenderLayer::Opaque
+        );
+        assert!(!blocks.block("dirt").unwrap().cull_same_block);
+
+        assert!(blocks.block("dirt").unwrap().solid());
+        assert!(!leaves.solid());
+        assert!(!water.solid());
+
+        assert!(blocks.transparent_blocks.contains(&leaves.id));
+        assert!(blocks.transparent_blocks.contains(&0));
+        assert!(!blocks.transparent_blocks.contains(&dirt));
+    }
+
+    #[test]
+    fn test_loading_blocks() {
+        let manager = BlockManager::from_bytes(
+            br#"{ "module": "rocks", "blocks": { "rock": { "name": "Rock", "durability": 2.0 } } }"#,
+        )
+        .unwrap();
+        assert_eq!(manager.block("rocks:rock").unwrap().name, "Rock");
+        assert!(manager.block("rock").is_none());
+        assert!(manager.block("dirt").is_none());
+        assert!(manager.validate(&BlockAssets::default()).is_ok());
+
+        assert!(matches!(
+            BlockManager::from_bytes(b"{ \"module\": \"rocks\", \"blocks\": [] }"),
+            Err(BlockLoadError::Parse(_))
+        ));
+        assert!(matches!(
+            load_blocks(&["missing/blocks.json"]),
+            Err(BlockLoadError::Io(_))
+        ));
+        assert!(matches!(
+            BlockManager::from_bytes(br#"{ "module": "a:b", "blocks": {} }"#),
+            Err(BlockLoadError::InvalidName(_))
+        ));
+        let manager = BlockManager::from_bytes(
+            br#"{ "module": "rocks", "blocks": { "rock": {
+                "name": "Rock", "durability": 2.0, "model": "boulder"
+            } } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            manager.validate(&BlockAssets::default()),
+            Err(BlockLoadError::MissingAsset { .. })
+        ));
+        let manager = BlockManager::from_bytes(
+            br#"{ "module": "rocks", "blocks": { "rock": {
+                "name": "Rock", "durability": 2.0,
+                "textures": { "all": { "texture": "rock", "uv": [16, 0, 0, 16] } }
+            } } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            manager.validate(&BlockAssets::default()),
+            Err(BlockLoadError::InvalidTexture { .. })
+        ));
+        for animation in [
+            r#"{ "frames": 0, "frame_time": 1.0 }"#,
+            r#"{ "frames": 2, "frame_time": 0.0 }"#,
+            r#"{ "frames": 2, "frame_time": -1.0 }"#,
+        ] {
+            let json = format!(
+                r#"{{ "module": "rocks", "blocks": {{ "lava": {{
+                    "name": "Lava", "durability": 2.0,
+                    "textures": {{ "all": {{ "texture": "lava", "animation": {} }} }}
+                }} }} }}"#,
+                animation
+            );
+            let manager = BlockManager::from_bytes(json.as_bytes()).unwrap();
+            assert!(matches!(
+                manager.validate(&BlockAssets::default()),
+                Err(BlockLoadError::InvalidTexture { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_module_assets() {
+        let addon = ModuleFiles {
+            blocks: br#"{ "module": "addon", "blocks": {
+                "pebble": { "name": "Pebble", "durability": 1.0, "model": "pebble" },
+                "moss": { "name": "Moss", "durability": 1.0, "tint": { "colormap": "moss" } },
+                "tall_grass": { "name": "Tall Grass", "durability": 0.0, "model": "cross" }
+            } }"#,
+            models: Some(
+                br#"{ "models": { "pebble": {
+                "boxes": [ { "from": [6, 0, 6], "to": [10, 2, 10] } ]
+            } } }"#,
+            ),
+            colormaps: Some(
+                br#"{ "colormaps": { "moss": { "seasons": [
+                [0, 100, 0], [0, 200, 0], [100, 100, 0], [50, 50, 50]
+            ] } } }"#,
+            ),
+        };
+        let definitions = build_definitions(&[addon]).unwrap();
+        let blocks = &definitions.blocks;
+        let pebble = blocks.block("addon:pebble").unwrap().id;
+        let model = definitions.models.model(pebble).unwrap();
+        assert_eq!(model.collision.len(), 1);
+        let moss = blocks.block("addon:moss").unwrap().id;
+        assert!(definitions
+            .colormaps
+            .colormap(moss, BlockFace::Top)
+            .is_some());
+        // Blocks can use the models of other modules too.
+        let tall_grass = blocks.block("addon:tall_grass").unwrap().id;
+        assert!(definitions.models.model(tall_grass).is_some());
+        let grass = blocks.block("grass").unwrap().id;
+        assert!(definitions
+            .colormaps
+            .colormap(grass, BlockFace::Top)
+            .is_some());
+
+        // Without its own files, the addon's model and colour map are missing.
+        let without_assets = ModuleFiles {
+            models: None,
+            colormaps: None,
+            ..addon
+        };
+        assert!(matches!(
+            build_definitions(&[without_assets]),
+            Err(BlockLoadError::MissingAsset { .. })
+        ));
+        // Modules can't both define the same model.
+        let duplicate = ModuleFiles {
+            models: Some(br#"{ "models": { "slab": { "boxes": [] } } }"#),
+            ..addon
+        };
+        assert!(matches!(
+            build_definitions(&[duplicate]),
+            Err(BlockLoadError::DuplicateAsset(name)) if name == "slab"
+        ));
+        // Replacing the default module replaces its models and colour maps too.
+        let basegame = ModuleFiles {
+            models: None,
+            ..ModuleFiles::EMBEDDED
+        };
+        assert!(matches!(
+            build_definitions(&[basegame]),
+            Err(BlockLoadError::MissingAsset { .. })
+        ));
+
+        // The models and colour maps next to a module's `blocks.json` are read with it.
+        let directory = std::env::temp_dir().join(format!("chunk_modules_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("blocks.json"), addon.blocks).unwrap();
+        fs::write(directory.join(MODELS_FILE), addon.models.unwrap()).unwrap();
+        let contents = ModuleContents::read(&directory.join("blocks.json")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(contents.models.as_deref(), addon.models);
+        assert!(contents.colormaps.is_none());
+    }
+
+    #[test]
+    fn test_modules() {
+        let addon: &[u8] = br#"{ "module": "addon", "blocks": {
+            "stone": { "name": "Addon Stone", "durability": 2.0 }
+        } }"#;
+        let manager = BlockManager::from_modules(&[BLOCKS_JSON.as_bytes(), addon]).unwrap();
+        // Modules can have blocks with the same name as each other.
+        assert_eq!(manager.block("stone").unwrap().name, "Stone");
+        assert_eq!(manager.block("basegame:stone").unwrap().name, "Stone");
+        assert_eq!(manager.block("addon:stone").unwrap().name, "Addon Stone");
+        assert_eq!(manager.block("addon:stone").unwrap().local_name(), "stone");
+        assert_eq!(manager.block("air").unwrap().id, 0);
+        assert!(matches!(
+            BlockManager::from_modules(&[addon, addon]),
+            Err(BlockLoadError::DuplicateModule(_))
+        ));
+
+        // The order modules are loaded in doesn't matter.
+        let reordered = BlockManager::from_modules(&[addon, BLOCKS_JSON.as_bytes()]).unwrap();
+        assert_eq!(reordered.id_table(), manager.id_table());
+
+        // Saved chunks are remapped when modules are added...
+        let remap = manager.remap_from(&BLOCK_MANAGER.get().id_table());
+        assert!(!remap.is_identity());
+        for block in BLOCK_MANAGER.get().blocks() {
+            assert_eq!(remap.get(block.id), manager.block(&block.key).unwrap().id);
+        }
+        // ...or removed, with blocks that no longer exist becoming air.
+        let remap = BLOCK_MANAGER.get().remap_from(&manager.id_table());
+        let addon_stone = manager.block("addon:stone").unwrap().id;
+        assert_eq!(remap.get(addon_stone), 0);
+        assert_eq!(remap.missing, vec!["addon:stone".to_string()]);
+        let dirt = manager.block("dirt").unwrap().id;
+        assert_eq!(
+            remap.get(dirt),
+            BLOCK_MANAGER.get().block("dirt").unwrap().id
+        );
+
+        assert!(BLOCK_MANAGER
+            .get()
+            .remap_from(&BLOCK_MANAGER.get().id_table())
+            .is_identity());
     }
 }
@@ -1,13 +1,18 @@
-use crate::{block::BlockID, constants::*, positions::*};
+use crate::{
+    block::{BlockID, BlockRemap},
+    constants::*,
+    positions::*,
+};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ChunkData {
     pub position: ChunkPos,
     // These fields are Box-ed to prevent the stack from overflowing.
     // We're storing a whole lot of data!
     pub terrain: Box<TerrainData>,
-    pub skylightlevel: Box<LightLevelData>,
+    pub lightlevel: Box<LightLevelData>,
+    pub climate: Box<ClimateData>,
 }
 
 impl ChunkData {
@@ -18,10 +23,14 @@ impl ChunkData {
                 [CHUNK_SIZE_X, CHUNK_SIZE_Y, CHUNK_SIZE_Z],
                 0,
             )),
-            skylightlevel: Box::new(LightLevelData::from_elem(
+            lightlevel: Box::new(LightLevelData::from_elem(
                 [CHUNK_SIZE_X, CHUNK_SIZE_Y, CHUNK_SIZE_Z],
                 0,
             )),
+            climate: Box::new(ClimateData::from_elem(
+                [CHUNK_SIZE_X, CHUNK_SIZE_Z],
+                [128, 128],
+            )),
         }
     }
     pub fn get(&self, position: LocalBlockPos) -> BlockID {
@@ -30,6 +39,88 @@ impl ChunkData {
     pub fn set(&mut self, position: LocalBlockPos, to: BlockID) {
         self.terrain[[position.x, position.y, position.z]] = to;
     }
+    /// Gets the sky light level at `position`.
+    pub fn sky_light(&self, position: LocalBlockPos) -> u8 {
+        self.lightlevel[[position.x, position.y, position.z]] >> 4
+    }
+    /// Gets the block light level at `position`.
+    pub fn block_light(&self, position: LocalBlockPos) -> u8 {
+        self.lightlevel[[position.x, position.y, position.z]] & 0x0F
+    }
+    /// Sets the sky light level at `position`. Only the lower 4 bits of `level` are kept.
+    pub fn set_sky_light(&mut self, position: LocalBlockPos, level: u8) {
+        let packed = &mut self.lightlevel[[position.x, position.y, position.z]];
+        *packed = (*packed & 0x0F) | (level << 4);
+    }
+    /// Sets the block light level at `position`. Only the lower 4 bits of `level` are kept.
+    pub fn set_block_light(&mut self, position: LocalBlockPos, level: u8) {
+        let packed = &mut self.lightlevel[[position.x, position.y, position.z]];
+        *packed = (*packed & 0xF0) | (level & 0x0F);
+    }
+    /// Gets the combined light level at `position`, i.e. the brighter of
+    /// its sky light and block light.
+    pub fn light(&self, position: LocalBlockPos) -> u8 {
+        self.sky_light(position).max(self.block_light(position))
+    }
+    /// Gets the temperature and humidity of the column at `x` and `z` (local-space).
+    pub fn climate(&self, x: usize, z: usize) -> [u8; 2] {
+        self.climate[[x, z]]
+    }
+    /// Sets the temperature and humidity of the column at `x` and `z` (local-space).
+    pub fn set_climate(&mut self, x: usize, z: usize, climate: [u8; 2]) {
+        self.climate[[x, z]] = climate;
+    }
+
+    /// Turns the block IDs this chunk was saved with into the current ones.
+    pub fn remap_blocks(&mut self, remap: &BlockRemap) {
+        if !remap.is_identity() {
+            self.terrain.mapv_inplace(|block_id| remap.get(block_id));
+        }
+    }
+
+    /// Returns the positions of every block whose terrain or light level is different
+    /// in `other`, which is expected to be a newer version of the same chunk.
+    pub fn changed_blocks(&self, other: &ChunkData) -> Vec<LocalBlockPos> {
+        ndarray::Zip::indexed(&*self.terrain)
+            .and(&*other.terrain)
+            .and(&*self.lightlevel)
+            .and(&*other.lightlevel)
+            .fold(
+                Vec::new(),
+                |mut changed, (x, y, z), a, b, light_a, light_b| {
+                    if a != b || light_a != light_b {
+                        changed.push(LocalBlockPos::new(x, y, z, self.position));
+                    }
+                    changed
+                },
+            )
+    }
+
+    /// Returns the sections with blocks on the edge this chunk shares with `neighbour`,
+    /// which are the only ones whose faces depend on it.
+    ///
+    /// Returns nothing if `neighbour` isn't one of this chunk's `adjacent` chunks.
+    pub fn sections_bordering(&self, neighbour: ChunkPos) -> Vec<usize> {
+        let edge: Vec<(usize, usize)> =
+            match (neighbour.x - self.position.x, neighbour.z - self.position.z) {
+                (1, 0) => (0..CHUNK_SIZE_Z).map(|z| (CHUNK_SIZE_X - 1, z)).collect(),
+                (-1, 0) => (0..CHUNK_SIZE_Z).map(|z| (0, z)).collect(),
+                (0, 1) => (0..CHUNK_SIZE_X).map(|x| (x, CHUNK_SIZE_Z - 1)).collect(),
+                (0, -1) => (0..CHUNK_SIZE_X).map(|x| (x, 0)).collect(),
+                _ => return Vec::new(),
+            };
+        (0..SECTIONS_PER_CHUNK)
+            .filter(|section| {
+                SectionPos::new(self.position, *section)
+                    .block_range()
+                    .any(|y| {
+                        edge.iter().any(|(x, z)| {
+                            self.get(LocalBlockPos::new(*x, y, *z, self.position)) != 0
+                        })
+                    })
+            })
+            .collect()
+    }
 
     /// Gets the y-level of the first air block at `x` and `z` (local-space).
     ///
@@ -52,3 +143,64 @@ impl std::fmt::Debug for ChunkData {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::block::BLOCK_MANAGER;
+
+    use super::*;
+
+    #[test]
+    fn test_light_packing() {
+        let mut data = ChunkData::new(ChunkPos::new(0, 0));
+        let position = LocalBlockPos::new(3, 100, 7, data.position);
+        data.set_sky_light(position, 15);
+        data.set_block_light(position, 6);
+        assert_eq!(data.sky_light(position), 15);
+        assert_eq!(data.block_light(position), 6);
+        assert_eq!(data.light(position), 15);
+        data.set_sky_light(position, 2);
+        assert_eq!(data.sky_light(position), 2);
+        assert_eq!(data.block_light(position), 6);
+        assert_eq!(data.light(position), 6);
+        // Neighbouring blocks are untouched.
+        assert_eq!(data.light(LocalBlockPos::new(3, 101, 7, data.position)), 0);
+    }
+
+    #[test]
+    fn test_changed_blocks() {
+        let data = ChunkData::new(ChunkPos::new(0, 0));
+        let mut newer = ChunkData::new(ChunkPos::new(0, 0));
+        assert!(data.changed_blocks(&newer).is_empty());
+        let placed = LocalBlockPos::new(1, 2, 3, data.position);
+        let lit = LocalBlockPos::new(4, 5, 6, data.position);
+        newer.set(placed, BLOCK_MANAGER.get().block("stone").unwrap().id);
+        newer.set_block_light(lit, 9);
+        assert_eq!(data.changed_blocks(&newer), vec![placed, lit]);
+    }
+
+    #[test]
+    fn test_sections_bordering() {
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let mut data = ChunkData::new(ChunkPos::new(0, 0));
+        let east = ChunkPos::new(1, 0);
+        assert!(data.sections_bordering(east).is_empty());
+        // On the eastern edge, in the second and last sections.
+        data.set(
+            LocalBlockPos::new(CHUNK_SIZE_X - 1, 40, 7, data.position),
+            stone,
+        );
+        data.set(
+            LocalBlockPos::new(CHUNK_SIZE_X - 1, 511, 0, data.position),
+            stone,
+        );
+        // Not on any edge.
+        data.set(LocalBlockPos::new(5, 100, 5, data.position), stone);
+        assert_eq!(data.sections_bordering(east), vec![1, 15]);
+        assert!(data.sections_bordering(ChunkPos::new(-1, 0)).is_empty());
+        // The second block is also on the northern edge.
+        assert_eq!(data.sections_bordering(ChunkPos::new(0, -1)), vec![15]);
+        // Diagonal chunks don't share an edge.
+        assert!(data.sections_bordering(ChunkPos::new(1, -1)).is_empty());
+    }
+}
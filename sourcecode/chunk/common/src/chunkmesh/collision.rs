@@ -0,0 +1,182 @@
+//! Collision shapes for chunks, made of boxes rather than the triangles of their meshes.
+//!
+//! Only solid blocks collide. Neighbouring solid blocks are merged into as few boxes
+//! as possible, while blocks with models use their model's collision boxes instead.
+
+use crate::{
+    block::{Block, BLOCK_MANAGER},
+    chunk::ChunkData,
+    constants::SECTION_SIZE_Y,
+    model::{CollisionBox, MODEL_MANAGER},
+    prelude::*,
+};
+
+/// Returns `true` if `block_id` is a solid cube, which can be merged with others.
+fn is_solid_cube(block_id: BlockID) -> bool {
+    block_id != 0
+        && MODEL_MANAGER.get().model(block_id).is_none()
+        && BLOCK_MANAGER
+            .get()
+            .block_by_id(block_id)
+            .is_some_and(Block::solid)
+}
+
+/// The collision boxes of a section of a chunk, in the same space as its mesh.
+pub struct ChunkCollisionData {
+    boxes: Vec<CollisionBox>,
+}
+
+impl ChunkCollisionData {
+    /// Builds the collision boxes of the section of `chunk_data` at height `section`.
+    ///
+    /// Unlike meshes, these don't depend on the chunks around it. The boxes are
+    /// positioned relative to the chunk, not the section.
+    pub fn new_from_section(chunk_data: &ChunkData, section: usize) -> Self {
+        let section = SectionPos::new(chunk_data.position, section);
+        let mut collision_data = Self { boxes: Vec::new() };
+        collision_data.add_greedy_boxes(chunk_data, section);
+        collision_data.add_model_boxes(chunk_data, section);
+        collision_data
+    }
+    /// Returns the collision boxes, as `[min, max]`.
+    pub fn boxes(&self) -> &[CollisionBox] {
+        &self.boxes
+    }
+    /// Covers the solid cubes in `section` with boxes.
+    ///
+    /// Each box starts at the first solid block that isn't covered yet, and is grown
+    /// as far as it can go along Z, then X, then Y.
+    fn add_greedy_boxes(&mut self, chunk_data: &ChunkData, section: SectionPos) {
+        let [width, height, depth] = [CHUNK_SIZE_X, SECTION_SIZE_Y, CHUNK_SIZE_Z];
+        let bottom = section.block_range().start;
+        let index = |x: usize, y: usize, z: usize| (x * height + y) * depth + z;
+        let mut solid = vec![false; width * height * depth];
+        for x in 0..width {
+            for y in 0..height {
+                for z in 0..depth {
+                    let position = LocalBlockPos::new(x, bottom + y, z, chunk_data.position);
+                    solid[index(x, y, z)] = is_solid_cube(chunk_data.get(position));
+                }
+            }
+        }
+        for x in 0..width {
+            for y in 0..height {
+                for z in 0..depth {
+                    if !solid[index(x, y, z)] {
+                        continue;
+                    }
+                    let mut size_z = 1;
+                    while z + size_z < depth && solid[index(x, y, z + size_z)] {
+                        size_z += 1;
+                    }
+                    let row_is_solid = |x: usize, y: usize, solid: &[bool]| {
+                        (z..z + size_z).all(|z| solid[index(x, y, z)])
+                    };
+                    let mut size_x = 1;
+                    while x + size_x < width && row_is_solid(x + size_x, y, &solid) {
+                        size_x += 1;
+                    }
+                    let mut size_y = 1;
+                    while y + size_y < height
+                        && (x..x + size_x).all(|x| row_is_solid(x, y + size_y, &solid))
+                    {
+                        size_y += 1;
+                    }
+                    for x in x..x + size_x {
+                        for y in y..y + size_y {
+                            solid[index(x, y, 0)..][z..z + size_z].fill(false);
+                        }
+                    }
+                    // Blocks span from y-1 to y.
+                    let y = (bottom + y) as f32 - 1.0;
+                    self.boxes.push([
+                        [x as f32, y, z as f32],
+                        [(x + size_x) as f32, y + size_y as f32, (z + size_z) as f32],
+                    ]);
+                }
+            }
+        }
+    }
+    /// Adds the collision boxes of the solid blocks with models in `section`.
+    fn add_model_boxes(&mut self, chunk_data: &ChunkData, section: SectionPos) {
+        let blocks = BLOCK_MANAGER.get();
+        let models = MODEL_MANAGER.get();
+        for x in 0..CHUNK_SIZE_X {
+            for y in section.block_range() {
+                for z in 0..CHUNK_SIZE_Z {
+                    let position = LocalBlockPos::new(x, y, z, chunk_data.position);
+                    let block_id = chunk_data.get(position);
+                    let model = match models.model(block_id) {
+                        Some(model) => model,
+                        None => continue,
+                    };
+                    if !blocks.block_by_id(block_id).is_some_and(Block::solid) {
+                        continue;
+                    }
+                    let origin = [x, y, z].map(|n| n as f32);
+                    self.boxes.extend(model.collision.iter().map(|corners| {
+                        corners.map(|point| [0, 1, 2].map(|axis| origin[axis] + point[axis]))
+                    }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunkmesh::tests::chunk_with_blocks;
+
+    /// Builds the collision boxes of `section` of a chunk containing only `blocks`.
+    fn collide_blocks(blocks: &[([usize; 3], BlockID)], section: usize) -> ChunkCollisionData {
+        ChunkCollisionData::new_from_section(&chunk_with_blocks(blocks), section)
+    }
+
+    #[test]
+    fn test_greedy_boxes() {
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let collision = collide_blocks(&[([5, 100, 5], stone)], 3);
+        assert_eq!(collision.boxes(), &[[[5.0, 99.0, 5.0], [6.0, 100.0, 6.0]]]);
+        assert!(collide_blocks(&[([5, 100, 5], stone)], 2)
+            .boxes()
+            .is_empty());
+
+        // A 4x3x4 cube becomes a single box.
+        let mut blocks = Vec::new();
+        for x in 4..8 {
+            for y in 70..73 {
+                for z in 4..8 {
+                    blocks.push(([x, y, z], stone));
+                }
+            }
+        }
+        let collision = collide_blocks(&blocks, 2);
+        assert_eq!(collision.boxes(), &[[[4.0, 69.0, 4.0], [8.0, 72.0, 8.0]]]);
+
+        // An L shape needs two.
+        blocks.push(([8, 70, 4], stone));
+        assert_eq!(collide_blocks(&blocks, 2).boxes().len(), 2);
+    }
+
+    #[test]
+    fn test_non_solid_blocks() {
+        let leaves = BLOCK_MANAGER.get().block("chestnut_leaves").unwrap().id;
+        let water = BLOCK_MANAGER.get().block("water").unwrap().id;
+        let flower = BLOCK_MANAGER.get().block("flower").unwrap().id;
+        let collision = collide_blocks(
+            &[
+                ([5, 10, 5], leaves),
+                ([6, 10, 5], water),
+                ([7, 10, 5], flower),
+            ],
+            0,
+        );
+        assert!(collision.boxes().is_empty());
+
+        // Models use their own collision boxes.
+        let slab = BLOCK_MANAGER.get().block("stone_slab").unwrap().id;
+        let collision = collide_blocks(&[([5, 10, 5], slab)], 0);
+        assert_eq!(collision.boxes(), &[[[5.0, 9.0, 5.0], [6.0, 9.5, 6.0]]]);
+    }
+}
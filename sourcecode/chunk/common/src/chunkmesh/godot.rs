@@ -0,0 +1,106 @@
+//! Converts chunk meshes into Godot resources.
+
+use gdnative::{
+    api::{ArrayMesh, BoxShape, Mesh},
+    core_types::{
+        Basis, Color, ColorArray, Int32Array, Transform, VariantArray, Vector2, Vector2Array,
+        Vector3, Vector3Array,
+    },
+    prelude::*,
+};
+
+use crate::{
+    chunkmesh::{
+        collision::ChunkCollisionData, material::MATERIALS, raw_mesh::MeshData, ChunkMeshData,
+    },
+    color, vec2, vec3,
+};
+
+/// Converts `mesh_data` into a `VariantArray` for use with
+/// Godot's `ArrayMesh` and its associated concepts.
+fn to_gd_array(mesh_data: &MeshData) -> VariantArray<Unique> {
+    let gdarray = VariantArray::new();
+    gdarray.resize(ArrayMesh::ARRAY_MAX as i32);
+    gdarray.set(
+        ArrayMesh::ARRAY_VERTEX as i32,
+        Vector3Array::from_iter(mesh_data.vertices.iter().map(|v| vec3!(v))),
+    );
+    // Normals are left out, see `raw_mesh::UPLOADED_VERTEX_SIZE`.
+    gdarray.set(
+        ArrayMesh::ARRAY_TEX_UV as i32,
+        Vector2Array::from_iter(mesh_data.uvs.iter().map(|v| vec2!(v))),
+    );
+    gdarray.set(
+        ArrayMesh::ARRAY_TEX_UV2 as i32,
+        Vector2Array::from_iter(mesh_data.uv2s.iter().map(|v| vec2!(v))),
+    );
+    gdarray.set(
+        ArrayMesh::ARRAY_COLOR as i32,
+        ColorArray::from_iter(mesh_data.colors.iter().map(|v| color!(v))),
+    );
+    gdarray.set(
+        ArrayMesh::ARRAY_INDEX as i32,
+        Int32Array::from_iter(mesh_data.indices.iter().map(|i| *i as i32)),
+    );
+    gdarray
+}
+
+/// Adds `array_data` to `mesh` as a new surface.
+///
+/// Everything but the vertex positions is compressed, which along with the
+/// missing normals is the layout `MeshData::uploaded_size` assumes.
+///
+/// Returns the new surface's "surface index".
+fn add_surface(array_data: VariantArray<Shared>, mesh: &ArrayMesh) -> i64 {
+    let surf_idx = mesh.get_surface_count();
+    mesh.add_surface_from_arrays(
+        Mesh::PRIMITIVE_TRIANGLES,
+        array_data,
+        VariantArray::new().into_shared(),
+        Mesh::ARRAY_COMPRESS_DEFAULT,
+    );
+    surf_idx
+}
+
+impl ChunkMeshData {
+    /// Constructs an `ArrayMesh` from this `ChunkMeshData`, with a surface
+    /// for each render layer using that layer's shared material.
+    ///
+    /// Surfaces whose layer has no material, because its shader couldn't be loaded,
+    /// are drawn with Godot's default material instead.
+    pub fn build_mesh(&self) -> Ref<ArrayMesh, Unique> {
+        let mesh = ArrayMesh::new();
+        let materials = MATERIALS.get();
+        for (layer, surface) in self.surfaces() {
+            let surf_idx = add_surface(to_gd_array(surface).into_shared(), &mesh);
+            if let Some(material) = materials.get(&layer) {
+                mesh.surface_set_material(surf_idx, material);
+            }
+        }
+        mesh
+    }
+}
+
+/// Constructs a `BoxShape` spanning from `min` to `max`, along with the transform
+/// that moves it into place.
+pub(crate) fn build_box_shape(min: Vector3, max: Vector3) -> (Ref<BoxShape, Unique>, Transform) {
+    let shape = BoxShape::new();
+    shape.set_extents((max - min) / 2.0);
+    let transform = Transform {
+        basis: Basis::IDENTITY,
+        origin: (min + max) / 2.0,
+    };
+    (shape, transform)
+}
+
+impl ChunkCollisionData {
+    /// Converts the boxes of this `ChunkCollisionData` into a `Vector3Array`,
+    /// with the minimum and maximum corners of each box one after the other.
+    pub fn to_gd_array(&self) -> Vector3Array {
+        Vector3Array::from_iter(
+            self.boxes()
+                .iter()
+                .flat_map(|[min, max]| [vec3!(min), vec3!(max)]),
+        )
+    }
+}
@@ -0,0 +1,308 @@
+//! Simplified meshes for distant chunks.
+//!
+//! At lower levels of detail, each section is split into cubic cells of blocks, and
+//! every cell is drawn as if it were one big block of whichever block is most common
+//! on its surface.
+
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::{
+    block::BLOCK_MANAGER,
+    chunk::ChunkData,
+    chunkmesh::{
+        raw_mesh::{Shading, FACES},
+        should_draw_face, ChunkMeshData, FaceAppearance,
+    },
+    constants::SECTION_SIZE_Y,
+    light::MAX_LIGHT,
+    model::MODEL_MANAGER,
+    prelude::*,
+};
+
+/// How far the skirts around the edges of simplified chunks hang below the surface,
+/// in blocks.
+///
+/// This is the size of the largest cells, so that skirts always cover the gap between
+/// two chunks drawn at different levels of detail.
+const SKIRT_DEPTH: usize = 8;
+
+/// How detailed a chunk's mesh is.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum Lod {
+    /// Every block is drawn.
+    Full,
+    /// Blocks are merged into cells of 2×2×2.
+    X2,
+    /// Blocks are merged into cells of 4×4×4.
+    X4,
+    /// Blocks are merged into cells of 8×8×8.
+    X8,
+}
+
+impl Lod {
+    /// Returns the level of detail for a chunk `distance` chunks away from the camera,
+    /// dropping a level every `step` chunks.
+    pub fn for_distance(distance: usize, step: usize) -> Self {
+        match distance / step.max(1) {
+            0 => Lod::Full,
+            1 => Lod::X2,
+            2 => Lod::X4,
+            _ => Lod::X8,
+        }
+    }
+
+    /// Returns the width of each cell of blocks, in blocks.
+    pub fn scale(&self) -> usize {
+        match self {
+            Lod::Full => 1,
+            Lod::X2 => 2,
+            Lod::X4 => 4,
+            Lod::X8 => 8,
+        }
+    }
+}
+
+/// Picks the block a cell of `scale` blocks starting at `origin` is drawn as,
+/// along with the brightest light level inside it.
+///
+/// Cells that are less than half full are empty (air). Otherwise, the block that
+/// makes up most of the cell's surface is used, so that e.g. grass stays green from
+/// far away even though there's more dirt beneath it.
+fn sample_cell(chunk_data: &ChunkData, origin: [usize; 3], scale: usize) -> (BlockID, u8) {
+    // Each block in the cell, with how many of it there are in total and on the surface.
+    let mut counts: Vec<(BlockID, usize, usize)> = Vec::new();
+    let mut filled = 0;
+    let mut light = 0;
+    for x in origin[0]..origin[0] + scale {
+        for y in origin[1]..origin[1] + scale {
+            for z in origin[2]..origin[2] + scale {
+                let position = LocalBlockPos::new(x, y, z, chunk_data.position);
+                light = light.max(chunk_data.light(position));
+                let block_id = chunk_data.get(position);
+                // Models are too small to see from far away.
+                if block_id == 0 || MODEL_MANAGER.get().model(block_id).is_some() {
+                    continue;
+                }
+                let surface = y + 1 == CHUNK_SIZE_Y
+                    || BLOCK_MANAGER.get().transparent_blocks.contains(
+                        &chunk_data.get(LocalBlockPos::new(x, y + 1, z, chunk_data.position)),
+                    );
+                let index = match counts.iter().position(|(id, ..)| *id == block_id) {
+                    Some(index) => index,
+                    None => {
+                        counts.push((block_id, 0, 0));
+                        counts.len() - 1
+                    }
+                };
+                counts[index].1 += 1;
+                counts[index].2 += surface as usize;
+                filled += 1;
+            }
+        }
+    }
+    if filled * 2 < scale.pow(3) {
+        return (0, light);
+    }
+    let (block_id, ..) = counts
+        .into_iter()
+        .max_by_key(|(block_id, total, surface)| (*surface, *total, Reverse(*block_id)))
+        .unwrap();
+    (block_id, light)
+}
+
+/// The cells of a section at some level of detail, along with the layer of cells
+/// around it, so that the faces at the section's edges can be checked.
+struct CellGrid {
+    /// How many cells wide the section is.
+    size: isize,
+    /// The block and light level of each cell, or `None` for cells
+    /// outside of the world or in unloaded chunks.
+    cells: Vec<Option<(BlockID, u8)>>,
+}
+
+impl CellGrid {
+    fn new(
+        chunk_data: &ChunkData,
+        section: SectionPos,
+        loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+        scale: usize,
+    ) -> Self {
+        let size = (SECTION_SIZE_Y / scale) as isize;
+        let bottom = section.block_range().start as isize;
+        let mut cells = Vec::with_capacity((size as usize + 2).pow(3));
+        for x in -1..=size {
+            for y in -1..=size {
+                for z in -1..=size {
+                    let origin = [
+                        x * scale as isize,
+                        bottom + y * scale as isize,
+                        z * scale as isize,
+                    ];
+                    if !(0..CHUNK_SIZE_Y as isize).contains(&origin[1]) {
+                        cells.push(None);
+                        continue;
+                    }
+                    let chunk = ChunkPos::new(
+                        section.chunk.x + origin[0].div_euclid(CHUNK_SIZE_X as isize),
+                        section.chunk.z + origin[2].div_euclid(CHUNK_SIZE_Z as isize),
+                    );
+                    let data = if chunk == chunk_data.position {
+                        Some(chunk_data)
+                    } else {
+                        loaded_chunks.get(&chunk).copied()
+                    };
+                    let local = [
+                        origin[0].rem_euclid(CHUNK_SIZE_X as isize) as usize,
+                        origin[1] as usize,
+                        origin[2].rem_euclid(CHUNK_SIZE_Z as isize) as usize,
+                    ];
+                    cells.push(data.map(|data| sample_cell(data, local, scale)));
+                }
+            }
+        }
+        Self { size, cells }
+    }
+
+    /// Returns the cell at `position`, measured in cells from the section's origin.
+    fn get(&self, position: [isize; 3]) -> Option<(BlockID, u8)> {
+        let [x, y, z] = position.map(|n| (n + 1) as usize);
+        let width = self.size as usize + 2;
+        self.cells[(x * width + y) * width + z]
+    }
+}
+
+impl ChunkMeshData {
+    /// Adds the faces of the simplified cells in `section`, which are `lod.scale()`
+    /// blocks wide.
+    ///
+    /// Faces on the sides of the chunk near the surface are replaced by skirts that hang
+    /// down past the cell, hiding any gaps between this chunk and neighbours that are
+    /// drawn at a different level of detail.
+    pub(super) fn add_lod_faces(
+        &mut self,
+        chunk_data: &ChunkData,
+        section: SectionPos,
+        loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+        lod: Lod,
+    ) {
+        let scale = lod.scale();
+        let grid = CellGrid::new(chunk_data, section, loaded_chunks, scale);
+        let bottom = section.block_range().start;
+        let is_see_through = |cell: Option<(BlockID, u8)>| {
+            cell.is_none_or(|(block_id, _)| {
+                BLOCK_MANAGER.get().transparent_blocks.contains(&block_id)
+            })
+        };
+        for x in 0..grid.size {
+            for y in 0..grid.size {
+                for z in 0..grid.size {
+                    let block_id = match grid.get([x, y, z]) {
+                        Some((block_id, _)) if block_id != 0 => block_id,
+                        _ => continue,
+                    };
+                    let above = grid.get([x, y + 1, z]);
+                    let is_surface = is_see_through(above);
+                    for face in &FACES {
+                        let [nx, ny, nz] = face.normal;
+                        let adjacent = grid.get([x + nx, y + ny, z + nz]);
+                        let on_chunk_edge = !(0..grid.size).contains(&(x + nx))
+                            || !(0..grid.size).contains(&(z + nz));
+                        let is_skirt = on_chunk_edge && is_surface;
+                        if !is_skirt && !should_draw_face(block_id, adjacent) {
+                            continue;
+                        }
+                        let mut origin = [x, y, z].map(|n| n as usize * scale);
+                        origin[1] += bottom;
+                        // Faces are placed on the far side of the block they belong to,
+                        // so use the cell's last block for faces pointing that way.
+                        let normal_axis = face.normal_axis();
+                        if face.normal[normal_axis] > 0 {
+                            origin[normal_axis] += scale - 1;
+                        }
+                        let mut size = [scale; 2];
+                        if is_skirt {
+                            // Side faces run along Y as their second UV axis.
+                            let top = origin[1] + scale;
+                            origin[1] = top.saturating_sub(scale + SKIRT_DEPTH);
+                            size[1] = top - origin[1];
+                        }
+                        // Skirts are lit like the top of the cell, since they're
+                        // usually facing into the neighbouring chunk's terrain.
+                        let lit_by = if is_skirt { above } else { adjacent };
+                        let [px, py, pz] = origin;
+                        let position = LocalBlockPos::new(px, py, pz, chunk_data.position);
+                        self.add_face(
+                            face,
+                            chunk_data,
+                            loaded_chunks,
+                            position,
+                            size,
+                            FaceAppearance {
+                                block_id,
+                                shading: Shading::unoccluded(
+                                    lit_by.map_or(MAX_LIGHT, |(_, light)| light),
+                                ),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::RenderLayer,
+        chunkmesh::{
+            tests::{mesh_blocks, vertex_count},
+            MeshingMode,
+        },
+    };
+
+    #[test]
+    fn test_lod() {
+        assert_eq!(Lod::for_distance(0, 3), Lod::Full);
+        assert_eq!(Lod::for_distance(3, 3), Lod::X2);
+        assert_eq!(Lod::for_distance(8, 3), Lod::X4);
+        assert_eq!(Lod::for_distance(100, 3), Lod::X8);
+
+        // An 8x8x8 cube lines up with a single cell at the lowest level of detail.
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let mut blocks = Vec::new();
+        for x in 8..16 {
+            for y in 64..72 {
+                for z in 8..16 {
+                    blocks.push(([x, y, z], stone));
+                }
+            }
+        }
+        let mesh = mesh_blocks(&blocks, 2, MeshingMode::Naive, Lod::X8);
+        assert_eq!(vertex_count(&mesh, RenderLayer::Opaque), 6 * 4);
+        let (_, surface) = mesh.surfaces().next().unwrap();
+        for axis in 0..3 {
+            let coordinates = surface.vertices.iter().map(|vertex| vertex[axis]);
+            let min = coordinates.clone().fold(f32::MAX, f32::min);
+            let max = coordinates.fold(f32::MIN, f32::max);
+            // Blocks span y-1..y rather than y..y+1.
+            let expected = if axis == 1 { [63.0, 71.0] } else { [8.0, 16.0] };
+            assert_eq!([min, max], expected);
+        }
+
+        // Cells that are mostly air are left out.
+        let mesh = mesh_blocks(&blocks[..200], 2, MeshingMode::Naive, Lod::X8);
+        assert_eq!(mesh.surfaces().count(), 0);
+
+        // Surface cells on the edge of the chunk hang skirts below them.
+        let edge: Vec<_> = blocks
+            .iter()
+            .map(|([x, y, z], block_id)| ([x - 8, *y, *z], *block_id))
+            .collect();
+        let mesh = mesh_blocks(&edge, 2, MeshingMode::Naive, Lod::X8);
+        let (_, surface) = mesh.surfaces().next().unwrap();
+        let lowest = surface.vertices.iter().map(|vertex| vertex[1]);
+        assert_eq!(lowest.fold(f32::MAX, f32::min), 63.0 - SKIRT_DEPTH as f32);
+    }
+}
@@ -1,65 +1,181 @@
 use std::collections::HashMap;
 
-use gdnative::{api::SpatialMaterial, prelude::*};
-use lazy_static::lazy_static;
+use gdnative::{
+    api::{Image, ImageTexture, Shader, ShaderMaterial, TextureArray},
+    prelude::*,
+};
 
-use crate::{block::BLOCKS_JSON, prelude::BlockID};
+use crate::{
+    block::RenderLayer,
+    reloadable::Reloadable,
+    texture::{TextureTile, MISSING_LAYER, TEXTURE_REGISTRY},
+    vec2,
+};
 
-/// Loads the texture for `block_id` from the game assets.
-fn get_albedo_texture(block_id: BlockID) -> Option<Ref<Texture, Shared>> {
-    let tex_path = format!("res://assets/textures/blocks/{}.png", block_id);
+/// The size of each tile in the block textures, in pixels.
+const TILE_SIZE: i64 = 16;
+
+/// Loads the block texture named `name` from the game assets.
+fn get_albedo_image(name: &str) -> Option<Ref<Image, Shared>> {
+    let tex_path = format!("res://assets/textures/blocks/{}.png", name);
     let resource_loader = ResourceLoader::godot_singleton();
+    if !resource_loader.exists(&tex_path, "") {
+        godot_warn!("Missing block texture {}", tex_path);
+        return None;
+    }
     let texture: Ref<Texture, Shared> = resource_loader.load(tex_path, "", false)?.cast().unwrap();
-    unsafe { texture.assume_safe() }.set_flags(Texture::FLAGS_DEFAULT ^ Texture::FLAG_FILTER);
-    Some(texture)
+    let image = unsafe { texture.assume_safe() }.get_data()?;
+    unsafe { image.assume_safe() }.convert(Image::FORMAT_RGBA8);
+    Some(image)
+}
+
+/// Creates an image to use for tiles whose texture is missing, and for the
+/// texture array's [`MISSING_LAYER`].
+fn missing_tile() -> Ref<Image, Shared> {
+    let image = Image::new();
+    image.create(TILE_SIZE, TILE_SIZE, false, Image::FORMAT_RGBA8);
+    image.fill(Color::from_rgb(1.0, 0.0, 1.0));
+    image.into_shared()
 }
 
-/// Creates the block-type-specific material for `block_id`.
+/// Cuts `tile` out of its texture, scaling it to fit a texture array layer.
 ///
-/// This uses the resource file for `block_id` in `assets/materials`
-/// if it exists, otherwise it creates a new `SpatialMaterial`.
-fn create_material(block_id: BlockID) -> Option<Ref<SpatialMaterial, Shared>> {
-    let resource_loader = ResourceLoader::godot_singleton();
-    let material_path = format!("res://assets/materials/{}.tres", block_id); // HARDCODED
-    let material = if resource_loader.exists(&material_path, "") {
-        // Prevent Godot error spam by checking for the material before attempting
-        // to load it.
-        resource_loader.load(material_path, "", false)
-    } else {
-        None
+/// Animated tiles are only one frame of their part of the texture.
+///
+/// Returns `None` if the texture is missing.
+fn get_tile_image(
+    tile: &TextureTile,
+    images: &mut HashMap<String, Option<Ref<Image, Shared>>>,
+) -> Option<Ref<Image, Shared>> {
+    let image = images
+        .entry(tile.texture.clone())
+        .or_insert_with(|| get_albedo_image(&tile.texture))
+        .as_ref()?;
+    let image = unsafe { image.assume_safe() };
+    let [x1, y1, x2, y2] =
+        tile.uv
+            .unwrap_or([0, 0, image.get_width() as u32, image.get_height() as u32]);
+    let frame_height = (y2 - y1) / tile.frames;
+    let y1 = y1 + frame_height * tile.frame;
+    let tile_image = image.get_rect(Rect2::new(vec2!(x1, y1), vec2!(x2 - x1, frame_height)))?;
+    let tile_image_ref = unsafe { tile_image.assume_safe() };
+    if tile_image_ref.get_width() != TILE_SIZE || tile_image_ref.get_height() != TILE_SIZE {
+        tile_image_ref.resize(TILE_SIZE, TILE_SIZE, Image::INTERPOLATE_NEAREST);
+    }
+    Some(tile_image)
+}
+
+/// Builds the texture array holding every block texture tile, each on its own layer.
+///
+/// See `TEXTURE_REGISTRY` for which layer each tile is on.
+fn create_texture_array() -> Ref<TextureArray, Shared> {
+    let registry = TEXTURE_REGISTRY.get();
+    let tiles = registry.tiles();
+    let texture_array = TextureArray::new();
+    // Nearest-neighbour filtering, no mipmaps.
+    texture_array.create(
+        TILE_SIZE,
+        TILE_SIZE,
+        tiles.len() as i64,
+        Image::FORMAT_RGBA8,
+        0,
+    );
+    let missing = missing_tile();
+    let mut images = HashMap::new();
+    for (layer, tile) in tiles.iter().enumerate() {
+        let tile_image = if layer as u32 == MISSING_LAYER {
+            missing.clone()
+        } else {
+            get_tile_image(tile, &mut images).unwrap_or_else(|| missing.clone())
+        };
+        texture_array.set_layer_data(tile_image, layer as i64);
+    }
+    texture_array.into_shared()
+}
+
+/// Builds a texture with one pixel for each layer of the texture array, describing the
+/// animation that starts on that layer: its number of frames, how long each frame lasts,
+/// and whether frames are interpolated. Layers that don't start an animation have 1 frame.
+///
+/// The shaders pick the current frame from this and `TIME`, which all materials share,
+/// so every animated block in the world plays in sync.
+fn create_animation_texture() -> Ref<ImageTexture, Shared> {
+    let registry = TEXTURE_REGISTRY.get();
+    let layers = registry.tiles().len() as i64;
+    let image = Image::new();
+    // Floats, so that frame times aren't limited to 0..1.
+    image.create(layers.max(1), 1, false, Image::FORMAT_RGBF);
+    image.lock();
+    for layer in 0..layers {
+        let color = match registry.animation(layer as u32) {
+            Some(animation) => Color::from_rgb(
+                animation.frames as f32,
+                animation.frame_time,
+                animation.interpolate as u8 as f32,
+            ),
+            None => Color::from_rgb(1.0, 1.0, 0.0),
+        };
+        image.set_pixel(layer, 0, color);
+    }
+    image.unlock();
+    let texture = ImageTexture::new();
+    // No filtering or mipmaps, so every pixel is read exactly as written.
+    texture.create_from_image(image, 0);
+    texture.into_shared()
+}
+
+/// Creates the shared material for surfaces in `layer`.
+///
+/// Returns `None` if its shader can't be loaded.
+fn create_material(
+    layer: RenderLayer,
+    textures: &Ref<TextureArray, Shared>,
+    animations: &Ref<ImageTexture, Shared>,
+) -> Option<Ref<ShaderMaterial, Shared>> {
+    let shader_path = match layer {
+        RenderLayer::Opaque => "res://assets/shaders/block.shader", // HARDCODED
+        RenderLayer::Cutout => "res://assets/shaders/block_cutout.shader", // HARDCODED
+        RenderLayer::Translucent => "res://assets/shaders/block_translucent.shader", // HARDCODED
     };
-    Some(match material {
-        Some(material) => material.cast().unwrap(),
+    let resource_loader = ResourceLoader::godot_singleton();
+    let shader: Ref<Shader, Shared> = match resource_loader.load(shader_path, "Shader", false) {
+        Some(shader) => shader.cast().unwrap(),
         None => {
-            // Make a new material containing the block's texture.
-            let material = SpatialMaterial::new();
-            material.set_texture(
-                SpatialMaterial::TEXTURE_ALBEDO,
-                get_albedo_texture(block_id)?,
+            godot_warn!(
+                "Couldn't load {}, {:?} blocks will use the default material",
+                shader_path,
+                layer
             );
-            material.set_flag(SpatialMaterial::FLAG_ALBEDO_FROM_VERTEX_COLOR, true);
-            material.set_flag(SpatialMaterial::FLAG_DISABLE_AMBIENT_LIGHT, true);
-            material.upcast::<SpatialMaterial>().into_shared()
+            return None;
         }
-    })
+    };
+    let material = ShaderMaterial::new();
+    material.set_shader(shader);
+    material.set_shader_param("block_textures", textures);
+    material.set_shader_param("block_animations", animations);
+    Some(material.into_shared())
 }
 
-lazy_static! {
-    pub static ref MATERIALS: HashMap<BlockID, Ref<SpatialMaterial, Shared>> = {
-        println!("Loading materials...");
-        // TODO: There's some code duplication with `block` here.
-        let mut game_dict: HashMap<String, serde_json::Value> =
-            serde_json::from_str(BLOCKS_JSON).unwrap();
-        let blocks: HashMap<String, serde_json::Map<String, serde_json::Value>> =
-            serde_json::from_value(game_dict.remove("blocks").unwrap()).unwrap();
-        let materials: HashMap<BlockID, Ref<SpatialMaterial, Shared>> = blocks
-            .into_iter()
-            .filter_map(|(_, block)| {
-                let id: BlockID = block.get("id").unwrap().as_u64().unwrap() as BlockID;
-                Some((id, create_material(id)?))
-            })
-            .collect();
-        println!("Loaded {} materials.", materials.len());
-        materials
-    };
+/// Creates the materials for every render layer, along with the textures they share.
+fn create_materials() -> HashMap<RenderLayer, Ref<ShaderMaterial, Shared>> {
+    println!("Loading materials...");
+    let textures = create_texture_array();
+    let animations = create_animation_texture();
+    let materials: HashMap<RenderLayer, Ref<ShaderMaterial, Shared>> = RenderLayer::ALL
+        .into_iter()
+        .filter_map(|layer| Some((layer, create_material(layer, &textures, &animations)?)))
+        .collect();
+    println!(
+        "Loaded {} materials, with {} texture layers.",
+        materials.len(),
+        TEXTURE_REGISTRY.get().tiles().len()
+    );
+    materials
 }
+
+/// The materials shared by every chunk mesh, one for each render layer.
+///
+/// These are rebuilt when the block definitions are reloaded, since their textures
+/// depend on them.
+pub static MATERIALS: Reloadable<HashMap<RenderLayer, Ref<ShaderMaterial, Shared>>> =
+    Reloadable::new(create_materials);
@@ -1,55 +1,49 @@
 //! Chunk mesh creation facilities and related nodes.
-mod material;
+//!
+//! Meshes are built as plain vertex data, which doesn't depend on Godot at all.
+//! Turning them into Godot resources and nodes is left to the `godot` module,
+//! along with `nodes`, which are only available with the `godot` feature.
+pub mod collision;
+#[cfg(feature = "godot")]
+mod godot;
+mod lod;
+#[cfg(feature = "godot")]
+pub(crate) mod material;
+#[cfg(feature = "godot")]
 pub mod nodes;
-pub(crate) mod raw_mesh;
+pub mod raw_mesh;
+pub mod visibility;
 
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
 };
 
-use gdnative::{
-    api::{ArrayMesh, ConcavePolygonShape},
-    core_types::{Vector3, Vector3Array},
-    prelude::*,
-};
-
 use crate::{
-    block::BLOCK_MANAGER,
+    block::{Block, RenderLayer, BLOCK_MANAGER},
     chunk::ChunkData,
-    chunkmesh::raw_mesh::{add_surface, Face, MeshData, FACES},
+    chunkmesh::{
+        raw_mesh::{corner_occlusion, light_brightness, Face, MeshData, Shading, FACES},
+        visibility::SectionVisibility,
+    },
+    colormap::{self, Colormap, COLORMAP_MANAGER},
+    constants::SECTION_SIZE_Y,
     errors::{NotLoadedError, OffsetError},
+    light::MAX_LIGHT,
+    model::{BlockModel, ModelQuad, MODEL_MANAGER},
+    positions::BlockOffset,
     prelude::*,
-    vec3,
+    texture::{BlockFace, TextureLayer, TEXTURE_REGISTRY},
 };
 
-use self::material::MATERIALS;
+pub use self::lod::Lod;
 
-/// Block-type specific `MeshData`, to allow for different block types
-/// to have their own specific materials.
-///
-/// Provides convenience functions like `BlockSurface.add_to_mesh` for
-/// adding to an `ArrayMesh` as a new surface, with the material specific
-/// to `self.block_id`.
-struct BlockSurface {
-    mesh_data: MeshData,
-    block_id: BlockID,
-}
-
-impl BlockSurface {
-    fn new(block_id: BlockID) -> Self {
-        Self {
-            mesh_data: MeshData::new(),
-            block_id,
-        }
-    }
-    /// Adds this `BlockSurface` to `mesh` as a surface,
-    /// setting the surface's material depending on `self.block_id`.
-    fn add_to_mesh(&self, mesh: &mut Ref<ArrayMesh, Unique>) {
-        let array_data = self.mesh_data.to_gd_array().into_shared();
-        let surf_idx = add_surface(array_data, mesh);
-        mesh.surface_set_material(surf_idx, MATERIALS.get(&self.block_id).unwrap());
-    }
+/// Returns the render layer the faces of `block_id` are drawn in.
+fn render_layer_of(block_id: BlockID) -> RenderLayer {
+    BLOCK_MANAGER
+        .get()
+        .block_by_id(block_id)
+        .map_or(RenderLayer::Opaque, Block::render_layer)
 }
 
 /// Gets the block at `position` from `position.chunk` if it is present in `loaded_chunks`.
@@ -63,24 +57,18 @@ fn get_global(
         .ok_or(NotLoadedError)
 }
 
-/// Returns `true` if:
-/// * The face is adjacent to a block that is transparent
-/// * The face is adjacent to a block that is in an unloaded chunk
-/// * The face is adjacent to a block that is outside of y=`0..512`
+/// Gets the block at `offset` from `position`, along with its combined light level.
 ///
-/// Returns `false` if:
-/// * The face is adjacent to a non-transparent block
-fn should_draw_face(
-    face: &Face,
+/// Returns `None` if that block is outside of y=`0..512`, or in an unloaded chunk.
+fn get_relative(
+    offset: BlockOffset,
     chunk_data: &ChunkData,
     loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
     position: LocalBlockPos,
-) -> bool {
-    let offset = face.normal.into();
-    let block_id = match position.offset(offset) {
-        Ok(position) => chunk_data.get(position),
-        // Draw faces at the bottom (y=0) and top (y=512) of the world.
-        Err(OffsetError::OutOfBounds) => return true,
+) -> Option<(BlockID, u8)> {
+    match position.offset(offset) {
+        Ok(position) => Some((chunk_data.get(position), chunk_data.light(position))),
+        Err(OffsetError::OutOfBounds) => None,
         Err(OffsetError::DifferentChunk) => {
             let position = position
                 .offset_global(offset)
@@ -88,100 +76,511 @@ fn should_draw_face(
                 // This is safe to unwrap because if it was an out-of-bounds position
                 // we would have already caught that above.
                 .unwrap();
-            match get_global(loaded_chunks, position) {
-                Ok(block_id) => block_id,
-                // Draw faces adjacent to unloaded chunks.
-                Err(_) => return true,
+            let block_id = get_global(loaded_chunks, position).ok()?;
+            Some((block_id, loaded_chunks[&position.chunk].light(position)))
+        }
+    }
+}
+
+/// Gets the block adjacent to `position` in the direction of `face`,
+/// along with its combined light level.
+///
+/// Returns `None` if that block is outside of y=`0..512`, or in an unloaded chunk.
+fn get_adjacent(
+    face: &Face,
+    chunk_data: &ChunkData,
+    loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    position: LocalBlockPos,
+) -> Option<(BlockID, u8)> {
+    get_relative(face.normal.into(), chunk_data, loaded_chunks, position)
+}
+
+/// Calculates the ambient occlusion level of each corner of `face`.
+///
+/// Only non-transparent blocks occlude, and blocks in unloaded chunks are treated as air.
+fn get_occlusion(
+    face: &Face,
+    chunk_data: &ChunkData,
+    loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    position: LocalBlockPos,
+) -> [u8; 4] {
+    let is_solid = |offset: [isize; 3]| {
+        get_relative(offset.into(), chunk_data, loaded_chunks, position).is_some_and(
+            |(block_id, _)| !BLOCK_MANAGER.get().transparent_blocks.contains(&block_id),
+        )
+    };
+    [0, 1, 2, 3].map(|corner| {
+        let [side1, side2, diagonal] = face.occluders(corner);
+        corner_occlusion(is_solid(side1), is_solid(side2), is_solid(diagonal))
+    })
+}
+
+/// How many columns on each side of a corner are blended into its tint.
+const TINT_BLEND_RADIUS: isize = 2;
+
+/// Calculates the colour `colormap` gives the corner at `[x, z]` of `chunk_data`, by
+/// averaging the colour of every column within `TINT_BLEND_RADIUS` of it. Blocks that
+/// share a corner get the same colour there, so colours change smoothly from one
+/// climate to the next.
+///
+/// Columns in chunks missing from `loaded_chunks` are left out.
+fn corner_tint(
+    colormap: &Colormap,
+    chunk_data: &ChunkData,
+    loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    [x, z]: [isize; 2],
+) -> [f32; 3] {
+    let season = colormap::season();
+    let mut sum = [0u32; 3];
+    let mut count = 0;
+    for column_x in x - TINT_BLEND_RADIUS..x + TINT_BLEND_RADIUS {
+        for column_z in z - TINT_BLEND_RADIUS..z + TINT_BLEND_RADIUS {
+            let [size_x, size_z] = [CHUNK_SIZE_X as isize, CHUNK_SIZE_Z as isize];
+            let chunk = ChunkPos::new(
+                chunk_data.position.x + column_x.div_euclid(size_x),
+                chunk_data.position.z + column_z.div_euclid(size_z),
+            );
+            let data = if chunk == chunk_data.position {
+                chunk_data
+            } else {
+                match loaded_chunks.get(&chunk) {
+                    Some(data) => data,
+                    None => continue,
+                }
+            };
+            let climate = data.climate(
+                column_x.rem_euclid(size_x) as usize,
+                column_z.rem_euclid(size_z) as usize,
+            );
+            let color = colormap.color(climate, season);
+            for channel in 0..3 {
+                sum[channel] += color[channel] as u32;
             }
+            count += 1;
         }
+    }
+    // The columns next to the corner are in the chunk being meshed, so are always counted.
+    sum.map(|channel| channel as f32 / count as f32)
+}
+
+/// Calculates the tint of each corner of the `face` of `block_id` at `position`,
+/// stretched to cover `size` blocks. See `corner_tint`.
+///
+/// Returns white if the face isn't tinted.
+fn get_tints(
+    block_id: BlockID,
+    face: &Face,
+    chunk_data: &ChunkData,
+    loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    position: LocalBlockPos,
+    size: [usize; 2],
+) -> [[u8; 3]; 4] {
+    let colormaps = COLORMAP_MANAGER.get();
+    let colormap = match colormaps.colormap(block_id, BlockFace::from_normal(face.normal)) {
+        Some(colormap) => colormap,
+        None => return [[255; 3]; 4],
+    };
+    face.scaled_corners(size).map(|[x, _, z]| {
+        let corner = [position.x as isize + x, position.z as isize + z];
+        corner_tint(colormap, chunk_data, loaded_chunks, corner).map(|channel| channel as u8)
+    })
+}
+
+/// Calculates the tint of each corner of a model `quad` of `block_id` at `position`,
+/// by blending between the tints of the block's own corners.
+///
+/// Returns white if the quad isn't tinted.
+fn get_model_tints(
+    block_id: BlockID,
+    quad: &ModelQuad,
+    chunk_data: &ChunkData,
+    loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    position: LocalBlockPos,
+) -> [[u8; 3]; 4] {
+    let colormaps = COLORMAP_MANAGER.get();
+    let colormap = match colormaps.colormap(block_id, quad.texture) {
+        Some(colormap) => colormap,
+        None => return [[255; 3]; 4],
     };
-    BLOCK_MANAGER.transparent_blocks.contains(&block_id)
+    let [x, z] = [position.x as isize, position.z as isize];
+    let [near, far] = [z, z + 1]
+        .map(|z| [x, x + 1].map(|x| corner_tint(colormap, chunk_data, loaded_chunks, [x, z])));
+    quad.corners.map(|[x, _, z]| {
+        let [x, z] = [x.clamp(0.0, 1.0), z.clamp(0.0, 1.0)];
+        [0, 1, 2].map(|channel| {
+            let near = near[0][channel] + (near[1][channel] - near[0][channel]) * x;
+            let far = far[0][channel] + (far[1][channel] - far[0][channel]) * x;
+            (near + (far - near) * z) as u8
+        })
+    })
+}
+
+/// Returns `true` if the face of `block_id` facing `adjacent` should be drawn:
+/// * The face is adjacent to a transparent block, unless it's the same block
+///   and that block culls faces against itself
+/// * The face is adjacent to a block that is in an unloaded chunk
+/// * The face is adjacent to a block that is outside of y=`0..512`
+///
+/// Returns `false` if:
+/// * The face is adjacent to a non-transparent block
+fn should_draw_face(block_id: BlockID, adjacent: Option<(BlockID, u8)>) -> bool {
+    match adjacent {
+        Some((adjacent_id, _)) if adjacent_id == block_id => {
+            BLOCK_MANAGER.get().transparent_blocks.contains(&block_id)
+                && !BLOCK_MANAGER
+                    .get()
+                    .block_by_id(block_id)
+                    .is_some_and(|block| block.cull_same_block)
+        }
+        Some((adjacent_id, _)) => BLOCK_MANAGER
+            .get()
+            .transparent_blocks
+            .contains(&adjacent_id),
+        // Draw faces at the bottom (y=0) and top (y=512) of the world,
+        // and faces adjacent to unloaded chunks.
+        None => true,
+    }
+}
+
+/// How `ChunkMeshData` turns the visible faces of a chunk into geometry.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MeshingMode {
+    /// A separate quad for every visible block face.
+    Naive,
+    /// Adjacent faces that look the same are merged into larger quads.
+    Greedy,
+}
+
+/// Everything that determines how a single block face looks, apart from its tint.
+///
+/// Faces are only merged by greedy meshing if these are all the same. Tints change
+/// from one block to the next, so they're worked out for the corners of each quad
+/// once faces have been merged instead, see `get_tints`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+struct FaceAppearance {
+    block_id: BlockID,
+    shading: Shading,
+}
+
+/// Returns how the `face` of the block at `position` looks,
+/// or `None` if it shouldn't be drawn at all.
+fn get_face_appearance(
+    face: &Face,
+    chunk_data: &ChunkData,
+    loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    position: LocalBlockPos,
+) -> Option<FaceAppearance> {
+    let block_id = chunk_data.get(position);
+    if block_id == 0 || MODEL_MANAGER.get().model(block_id).is_some() {
+        // Air blocks have no faces, and blocks with models are added separately.
+        return None;
+    }
+    let adjacent = get_adjacent(face, chunk_data, loaded_chunks, position);
+    if !should_draw_face(block_id, adjacent) {
+        return None;
+    }
+    Some(FaceAppearance {
+        block_id,
+        shading: Shading {
+            // Faces with nothing in front of them are lit by the sky.
+            light: adjacent.map_or(MAX_LIGHT, |(_, light)| light),
+            occlusion: get_occlusion(face, chunk_data, loaded_chunks, position),
+            tint: [[255; 3]; 4],
+        },
+    })
 }
 
 /// Chunk mesh information, such as vertices.
 ///
-/// Mesh information is stored per-block-type as `BlockSurface`s.
+/// Mesh information is stored per-`RenderLayer`, with each layer becoming
+/// a separate surface of the mesh.
+///
+/// Collision is built separately, see [`collision::ChunkCollisionData`].
 pub struct ChunkMeshData {
-    surfaces: HashMap<BlockID, BlockSurface>,
+    surfaces: HashMap<RenderLayer, MeshData>,
+    /// Which faces of the section can see each other, for cave culling.
+    visibility: SectionVisibility,
 }
 
 impl ChunkMeshData {
-    fn new() -> Self {
+    fn new(visibility: SectionVisibility) -> Self {
         Self {
             surfaces: HashMap::new(),
+            visibility,
         }
     }
-    /// Adds a block face to this `ChunkMeshData`, putting it in the appropriate `BlockSurface`.
-    fn add_face(&mut self, block_id: BlockID, face: &Face, position: LocalBlockPos) {
-        let block_surface = self
-            .surfaces
-            .entry(block_id)
-            .or_insert_with(|| BlockSurface::new(block_id));
-        block_surface.mesh_data.add_face(
+    /// Adds a block face to this `ChunkMeshData`, putting it in the surface of its render layer.
+    ///
+    /// The face covers `size` blocks along its UV axes, starting at `position`, and is
+    /// tinted according to the columns around its corners.
+    fn add_face(
+        &mut self,
+        face: &Face,
+        chunk_data: &ChunkData,
+        loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+        position: LocalBlockPos,
+        size: [usize; 2],
+        appearance: FaceAppearance,
+    ) {
+        let tint = get_tints(
+            appearance.block_id,
+            face,
+            chunk_data,
+            loaded_chunks,
+            position,
+            size,
+        );
+        let layer = render_layer_of(appearance.block_id);
+        let texture = TEXTURE_REGISTRY
+            .get()
+            .layer(appearance.block_id, BlockFace::from_normal(face.normal))
+            .unwrap_or(TextureLayer::MISSING);
+        self.surfaces.entry(layer).or_default().add_face(
             face,
             [
                 position.x as isize,
                 position.y as isize,
                 position.z as isize,
             ],
+            size,
+            texture,
+            Shading {
+                tint,
+                ..appearance.shading
+            },
         );
     }
-    /// Constructs an `ArrayMesh` from this `ChunkMeshData`.
-    pub fn build_mesh(&self) -> Ref<ArrayMesh, Unique> {
-        let mut mesh = ArrayMesh::new();
-        for block_surface in self.surfaces.values() {
-            block_surface.add_to_mesh(&mut mesh);
+    /// Adds the model of the block at `position` to this `ChunkMeshData`,
+    /// skipping any quads that are culled by the blocks around it.
+    fn add_model(
+        &mut self,
+        model: &BlockModel,
+        chunk_data: &ChunkData,
+        loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+        position: LocalBlockPos,
+    ) {
+        let block_id = chunk_data.get(position);
+        let layer = render_layer_of(block_id);
+        // Models are lit by the light inside their own block.
+        let shade = light_brightness(chunk_data.light(position));
+        let origin = [position.x, position.y, position.z].map(|n| n as f32);
+        for quad in &model.quads {
+            if let Some(cull) = quad.cull {
+                let adjacent =
+                    get_relative(cull.normal().into(), chunk_data, loaded_chunks, position);
+                if !should_draw_face(block_id, adjacent) {
+                    continue;
+                }
+            }
+            let texture = TEXTURE_REGISTRY
+                .get()
+                .layer(block_id, quad.texture)
+                .unwrap_or(TextureLayer::MISSING);
+            let corners = quad
+                .corners
+                .map(|corner| [0, 1, 2].map(|axis| origin[axis] + corner[axis]));
+            let tints = get_model_tints(block_id, quad, chunk_data, loaded_chunks, position);
+            self.surfaces.entry(layer).or_default().add_quad(
+                corners,
+                quad.normal,
+                quad.uvs,
+                texture,
+                [shade; 4],
+                tints,
+            );
         }
-        mesh
-    }
-    /// Constructs a `ConcavePolygonShape` from this `ChunkMeshData`.
-    pub fn build_collision_shape(&self) -> Ref<ConcavePolygonShape, Unique> {
-        // Pool every vertex from every block type in the mesh data together.
-        let collision_shape = ConcavePolygonShape::new();
-        collision_shape.set_faces(Vector3Array::from_iter(
-            // Get the vertices from every BlockSurface.
-            self.surfaces
-                .values()
-                .flat_map(|bs| &bs.mesh_data.vertices)
-                // Convert them into Vector3s so that Vector3Array will accept them.
-                .map(|v| vec3!(v)),
-        ));
-        collision_shape
-    }
-    pub fn new_from_chunk_data(
+    }
+    /// Returns the mesh data of each render layer that has any faces.
+    pub fn surfaces(&self) -> impl Iterator<Item = (RenderLayer, &MeshData)> {
+        self.surfaces
+            .iter()
+            .map(|(layer, surface)| (*layer, surface))
+    }
+    /// Returns which faces of the section can see each other through its blocks.
+    pub fn visibility(&self) -> SectionVisibility {
+        self.visibility
+    }
+    /// Returns how many bytes this mesh takes up once uploaded to the GPU, followed by
+    /// how many it would if it wasn't indexed and had normals. See
+    /// [`MeshData::uploaded_size`] and [`MeshData::unindexed_uploaded_size`].
+    pub fn uploaded_size(&self) -> [usize; 2] {
+        self.surfaces
+            .values()
+            .fold([0, 0], |[indexed, unindexed], surface| {
+                [
+                    indexed + surface.uploaded_size(),
+                    unindexed + surface.unindexed_uploaded_size(),
+                ]
+            })
+    }
+    /// Builds the mesh data for the section of `chunk_data` at height `section`,
+    /// using `mode` to decide how faces become quads.
+    ///
+    /// `loaded_chunks` are used to check the faces at the chunk's edges. The mesh is
+    /// positioned relative to the chunk, not the section.
+    ///
+    /// Anything less detailed than `Lod::Full` is always meshed naively, and skips
+    /// block models entirely.
+    pub fn new_from_section(
         chunk_data: &ChunkData,
+        section: usize,
         loaded_chunks: HashMap<ChunkPos, &ChunkData>,
+        mode: MeshingMode,
+        lod: Lod,
     ) -> Self {
-        let mut chunk_mesh = Self::new();
+        let mut chunk_mesh = Self::new(SectionVisibility::new_from_section(chunk_data, section));
+        let section = SectionPos::new(chunk_data.position, section);
+        if lod != Lod::Full {
+            chunk_mesh.add_lod_faces(chunk_data, section, &loaded_chunks, lod);
+            return chunk_mesh;
+        }
+        match mode {
+            MeshingMode::Naive => chunk_mesh.add_naive_faces(chunk_data, section, &loaded_chunks),
+            MeshingMode::Greedy => chunk_mesh.add_greedy_faces(chunk_data, section, &loaded_chunks),
+        }
+        chunk_mesh.add_models(chunk_data, section, &loaded_chunks);
+        chunk_mesh
+    }
+    /// Adds the models of every block in `section` that has one.
+    fn add_models(
+        &mut self,
+        chunk_data: &ChunkData,
+        section: SectionPos,
+        loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    ) {
         for x in 0..CHUNK_SIZE_X {
-            for y in 0..CHUNK_SIZE_Y {
+            for y in section.block_range() {
+                for z in 0..CHUNK_SIZE_Z {
+                    let position = LocalBlockPos::new(x, y, z, chunk_data.position);
+                    if let Some(model) = MODEL_MANAGER.get().model(chunk_data.get(position)) {
+                        self.add_model(model, chunk_data, loaded_chunks, position);
+                    }
+                }
+            }
+        }
+    }
+    /// Adds a quad for every visible face in `section`.
+    fn add_naive_faces(
+        &mut self,
+        chunk_data: &ChunkData,
+        section: SectionPos,
+        loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    ) {
+        for x in 0..CHUNK_SIZE_X {
+            for y in section.block_range() {
                 for z in 0..CHUNK_SIZE_Z {
                     let position = LocalBlockPos::new(x, y, z, chunk_data.position);
-                    let block_id = chunk_data.get(position);
-                    if block_id == 0 {
-                        // This is an air block, it has no faces.
-                        continue;
-                    };
                     for face in &FACES {
-                        if should_draw_face(face, &*chunk_data, &loaded_chunks, position) {
-                            chunk_mesh.add_face(block_id, face, position);
+                        if let Some(appearance) =
+                            get_face_appearance(face, chunk_data, loaded_chunks, position)
+                        {
+                            self.add_face(
+                                face,
+                                chunk_data,
+                                loaded_chunks,
+                                position,
+                                [1, 1],
+                                appearance,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Adds the visible faces in `section`, merging adjacent faces that look the same.
+    ///
+    /// Each layer of blocks is handled separately for every face direction. The faces in
+    /// a layer are gathered into a mask, which is then covered with as few rectangles
+    /// as possible by growing each one as wide, and then as tall, as it can go.
+    fn add_greedy_faces(
+        &mut self,
+        chunk_data: &ChunkData,
+        section: SectionPos,
+        loaded_chunks: &HashMap<ChunkPos, &ChunkData>,
+    ) {
+        let dimensions = [CHUNK_SIZE_X, SECTION_SIZE_Y, CHUNK_SIZE_Z];
+        let bottom = section.block_range().start;
+        for face in &FACES {
+            let normal_axis = face.normal_axis();
+            let [u_axis, v_axis] = face.uv_axes();
+            let (width, height) = (dimensions[u_axis], dimensions[v_axis]);
+            let position_at = |layer: usize, u: usize, v: usize| {
+                let mut coordinates = [0; 3];
+                coordinates[normal_axis] = layer;
+                coordinates[u_axis] = u;
+                coordinates[v_axis] = v;
+                let [x, y, z] = coordinates;
+                let y = bottom + y;
+                LocalBlockPos::new(x, y, z, chunk_data.position)
+            };
+            let mut mask = vec![None; width * height];
+            for layer in 0..dimensions[normal_axis] {
+                for v in 0..height {
+                    for u in 0..width {
+                        mask[v * width + u] = get_face_appearance(
+                            face,
+                            chunk_data,
+                            loaded_chunks,
+                            position_at(layer, u, v),
+                        );
+                    }
+                }
+                for v in 0..height {
+                    let mut u = 0;
+                    while u < width {
+                        let appearance = match mask[v * width + u] {
+                            Some(appearance) => appearance,
+                            None => {
+                                u += 1;
+                                continue;
+                            }
                         };
+                        let mut quad_width = 1;
+                        while u + quad_width < width
+                            && mask[v * width + u + quad_width] == Some(appearance)
+                        {
+                            quad_width += 1;
+                        }
+                        let mut quad_height = 1;
+                        while v + quad_height < height
+                            && mask[(v + quad_height) * width + u..][..quad_width]
+                                .iter()
+                                .all(|other| *other == Some(appearance))
+                        {
+                            quad_height += 1;
+                        }
+                        for row in v..v + quad_height {
+                            mask[row * width + u..][..quad_width].fill(None);
+                        }
+                        self.add_face(
+                            face,
+                            chunk_data,
+                            loaded_chunks,
+                            position_at(layer, u, v),
+                            [quad_width, quad_height],
+                            appearance,
+                        );
+                        u += quad_width;
                     }
                 }
             }
         }
-        chunk_mesh
     }
-    /// A thread-safe version of [`Self::new_from_chunk_data`].
+    /// A thread-safe version of [`Self::new_from_section`], which builds the meshes
+    /// of several `sections` of the same chunk at once, all at the same `lod`.
     ///
     /// Locks `chunk_data` and all of its neighbors within `guarded_chunks`,
     /// and unlocks them once mesh generation is complete.
     ///
-    /// Returns the new mesh.
-    pub fn new_from_chunk_data_threaded(
+    /// Returns the new meshes, along with the sections they belong to.
+    pub fn new_from_sections_threaded(
         chunk_data: Arc<RwLock<ChunkData>>,
         guarded_chunks: HashMap<ChunkPos, Arc<RwLock<ChunkData>>>,
-    ) -> Self {
+        sections: &[usize],
+        mode: MeshingMode,
+        lod: Lod,
+    ) -> Vec<(usize, Self)> {
         let chunk_data = chunk_data.read().unwrap();
         let adjacent = chunk_data.position.adjacent();
 
@@ -200,10 +599,197 @@ impl ChunkMeshData {
             .filter(|(pos, _data)| adjacent.contains(pos))
             .map(|(pos, data)| (*pos, data.read().unwrap()))
             .collect();
-        let loaded_chunks = read_guards
+        let loaded_chunks: HashMap<ChunkPos, &ChunkData> = read_guards
             .iter()
             .map(|(pos, data)| (*pos, /* what the FUCK! */ &*(*data)))
             .collect();
-        Self::new_from_chunk_data(&*chunk_data, loaded_chunks)
+        sections
+            .iter()
+            .map(|section| {
+                let mesh_data =
+                    Self::new_from_section(&chunk_data, *section, loaded_chunks.clone(), mode, lod);
+                (*section, mesh_data)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the chunk at the origin containing only `blocks`.
+    pub(super) fn chunk_with_blocks(blocks: &[([usize; 3], BlockID)]) -> ChunkData {
+        let mut chunk_data = ChunkData::new(ChunkPos::new(0, 0));
+        for ([x, y, z], block_id) in blocks {
+            chunk_data.set(
+                LocalBlockPos::new(*x, *y, *z, chunk_data.position),
+                *block_id,
+            );
+        }
+        chunk_data
+    }
+
+    /// Builds the mesh of `section` of a chunk containing only `blocks`,
+    /// with no chunks loaded around it.
+    pub(super) fn mesh_blocks(
+        blocks: &[([usize; 3], BlockID)],
+        section: usize,
+        mode: MeshingMode,
+        lod: Lod,
+    ) -> ChunkMeshData {
+        let chunk_data = chunk_with_blocks(blocks);
+        ChunkMeshData::new_from_section(&chunk_data, section, HashMap::new(), mode, lod)
+    }
+
+    /// Returns how many vertices are in the surface for `layer`.
+    pub(super) fn vertex_count(mesh: &ChunkMeshData, layer: RenderLayer) -> usize {
+        mesh.surfaces()
+            .find(|(surface_layer, _)| *surface_layer == layer)
+            .map_or(0, |(_, surface)| surface.vertices.len())
+    }
+
+    #[test]
+    fn test_single_block() {
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let mesh = mesh_blocks(&[([5, 100, 5], stone)], 3, MeshingMode::Naive, Lod::Full);
+        assert_eq!(vertex_count(&mesh, RenderLayer::Opaque), 6 * 4);
+        assert_eq!(mesh.surfaces().count(), 1);
+        assert_eq!(mesh.uploaded_size(), [6 * (4 * 24 + 6 * 2), 6 * 6 * 28]);
+
+        // The block isn't in any other section.
+        let mesh = mesh_blocks(&[([5, 100, 5], stone)], 2, MeshingMode::Naive, Lod::Full);
+        assert_eq!(mesh.surfaces().count(), 0);
+    }
+
+    #[test]
+    fn test_greedy_meshing() {
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let mut blocks = Vec::new();
+        for x in 4..8 {
+            for z in 4..8 {
+                blocks.push(([x, 70, z], stone));
+            }
+        }
+        let naive = mesh_blocks(&blocks, 2, MeshingMode::Naive, Lod::Full);
+        // 16 faces on the top and bottom, and 4 on each side.
+        assert_eq!(
+            vertex_count(&naive, RenderLayer::Opaque),
+            (16 * 2 + 4 * 4) * 4
+        );
+        let greedy = mesh_blocks(&blocks, 2, MeshingMode::Greedy, Lod::Full);
+        assert_eq!(vertex_count(&greedy, RenderLayer::Opaque), 6 * 4);
+
+        // The merged faces' UVs span all 4 blocks, so the texture tiles.
+        let (_, surface) = greedy.surfaces().next().unwrap();
+        let uv_max = surface
+            .uvs
+            .iter()
+            .flatten()
+            .fold(0.0, |a: f32, &b| a.max(b));
+        assert_eq!(uv_max, 4.0);
+
+        // Faces of different blocks aren't merged.
+        let dirt = BLOCK_MANAGER.get().block("dirt").unwrap().id;
+        blocks[5].1 = dirt;
+        let greedy = mesh_blocks(&blocks, 2, MeshingMode::Greedy, Lod::Full);
+        assert!(vertex_count(&greedy, RenderLayer::Opaque) > 6 * 4);
+    }
+
+    #[test]
+    fn test_tint() {
+        let grass = BLOCK_MANAGER.get().block("grass").unwrap().id;
+        let mut chunk_data = ChunkData::new(ChunkPos::new(0, 0));
+        for x in 0..CHUNK_SIZE_X {
+            for z in 0..CHUNK_SIZE_Z {
+                chunk_data.set_climate(x, z, [(x * 8) as u8, 128]);
+            }
+        }
+        for x in 4..8 {
+            for z in 4..8 {
+                chunk_data.set(LocalBlockPos::new(x, 70, z, chunk_data.position), grass);
+                let above = LocalBlockPos::new(x, 71, z, chunk_data.position);
+                chunk_data.set_sky_light(above, MAX_LIGHT);
+            }
+        }
+        let top_colors = |mode| {
+            let mesh =
+                ChunkMeshData::new_from_section(&chunk_data, 2, HashMap::new(), mode, Lod::Full);
+            let (_, surface) = mesh.surfaces().next().unwrap();
+            let mut colors = HashMap::new();
+            let vertices = surface.vertices.iter().zip(&surface.normals);
+            for ((vertex, normal), color) in vertices.zip(&surface.colors) {
+                if *normal == [0.0, 1.0, 0.0] {
+                    let corner = [vertex[0] as usize, vertex[2] as usize];
+                    assert_eq!(*colors.entry(corner).or_insert(*color), *color);
+                }
+            }
+            (surface.quad_count(), colors)
+        };
+
+        // Neighbouring faces agree on the colour of the corners they share,
+        // even though the colour changes along X.
+        let (_, naive) = top_colors(MeshingMode::Naive);
+        assert_eq!(naive.len(), 5 * 5);
+        assert_ne!(naive[&[4, 4]], naive[&[8, 4]]);
+        // Tinted faces are still merged, and the merged quad has the same corners.
+        let (quads, greedy) = top_colors(MeshingMode::Greedy);
+        assert_eq!(quads, 6);
+        assert_eq!(greedy.len(), 4);
+        for (corner, color) in greedy {
+            assert_eq!(naive[&corner], color);
+        }
+    }
+
+    #[test]
+    fn test_render_layers() {
+        let leaves = BLOCK_MANAGER.get().block("chestnut_leaves").unwrap().id;
+        let glass = BLOCK_MANAGER.get().block("glass").unwrap().id;
+        let torch = BLOCK_MANAGER.get().block("torch").unwrap().id;
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let pair = |block_id| [([5, 10, 5], block_id), ([6, 10, 5], block_id)];
+
+        // Leaves and glass hide the faces between two of themselves.
+        let mesh = mesh_blocks(&pair(leaves), 0, MeshingMode::Naive, Lod::Full);
+        assert_eq!(vertex_count(&mesh, RenderLayer::Cutout), 10 * 4);
+        assert_eq!(vertex_count(&mesh, RenderLayer::Opaque), 0);
+        let mesh = mesh_blocks(&pair(glass), 0, MeshingMode::Naive, Lod::Full);
+        assert_eq!(vertex_count(&mesh, RenderLayer::Translucent), 10 * 4);
+        // Other transparent blocks don't.
+        let mesh = mesh_blocks(&pair(torch), 0, MeshingMode::Naive, Lod::Full);
+        assert_eq!(vertex_count(&mesh, RenderLayer::Cutout), 12 * 4);
+
+        // An opaque block's face is still drawn next to a transparent one.
+        let mesh = mesh_blocks(
+            &[([5, 10, 5], stone), ([6, 10, 5], glass)],
+            0,
+            MeshingMode::Naive,
+            Lod::Full,
+        );
+        assert_eq!(vertex_count(&mesh, RenderLayer::Opaque), 6 * 4);
+        assert_eq!(vertex_count(&mesh, RenderLayer::Translucent), 5 * 4);
+
+        // Different translucent blocks still draw the faces between them.
+        let water = BLOCK_MANAGER.get().block("water").unwrap().id;
+        let mesh = mesh_blocks(
+            &[([5, 10, 5], water), ([6, 10, 5], glass)],
+            0,
+            MeshingMode::Naive,
+            Lod::Full,
+        );
+        assert_eq!(vertex_count(&mesh, RenderLayer::Translucent), 12 * 4);
+
+        // Each layer gets its own surface.
+        let mesh = mesh_blocks(
+            &[
+                ([5, 10, 5], stone),
+                ([5, 12, 5], leaves),
+                ([5, 14, 5], glass),
+            ],
+            0,
+            MeshingMode::Naive,
+            Lod::Full,
+        );
+        assert_eq!(mesh.surfaces().count(), 3);
     }
 }
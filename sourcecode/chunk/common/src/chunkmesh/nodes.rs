@@ -1,38 +1,68 @@
 //! Chunk nodes, for use by the server and client.
 
 use gdnative::{
-    api::{CollisionShape, MeshInstance, StaticBody},
+    api::{MeshInstance, StaticBody},
     prelude::*,
 };
 
-use crate::{chunkmesh::ChunkMeshData, prelude::ChunkPos, vec3};
+use crate::{
+    chunkmesh::{collision::ChunkCollisionData, godot::build_box_shape, ChunkMeshData},
+    constants::SECTIONS_PER_CHUNK,
+    prelude::ChunkPos,
+    vec3,
+};
 
+/// The collision boxes of one section of a chunk.
+///
+/// The boxes are added as shapes of the `StaticBody` this node is a child of,
+/// since a `CollisionShape` can only hold a single shape.
 #[derive(NativeClass)]
 #[export]
-#[inherit(CollisionShape)]
+#[inherit(Node)]
 #[user_data(gdnative::export::user_data::MutexData<ChunkCollisionShape>)]
 pub struct ChunkCollisionShape {
-    base: Ref<CollisionShape, Shared>,
+    base: Ref<Node, Shared>,
+    /// The shape owners of the boxes currently in the body, one per box.
+    owners: Vec<i64>,
 }
 
 #[methods]
 impl ChunkCollisionShape {
-    fn new(base: &CollisionShape) -> Self {
+    fn new(base: &Node) -> Self {
         Self {
             base: unsafe { base.assume_shared() },
+            owners: Vec::new(),
         }
     }
-    fn update(&mut self, mesh_data: &ChunkMeshData) {
-        let new_collision_shape = mesh_data.build_collision_shape();
+    fn update(&mut self, collision_data: &ChunkCollisionData) {
         unsafe {
-            // This MUST be call_deferred, setting the shape when using the
+            // This MUST be call_deferred, changing shapes when using the
             // Bullet physics engine is NOT thread-safe!
-            self.base.assume_safe().call_deferred(
-                "set_shape",
-                &[new_collision_shape.into_shared().to_variant()],
-            );
+            self.base
+                .assume_safe()
+                .call_deferred("set_boxes", &[collision_data.to_gd_array().to_variant()]);
         };
     }
+
+    #[export]
+    /// Replaces this section's boxes in the body with `boxes`, which holds
+    /// the minimum and maximum corners of each box one after the other.
+    fn set_boxes(&mut self, base: &Node, boxes: Vector3Array) {
+        let body = base
+            .get_parent()
+            .and_then(|parent| unsafe { parent.assume_safe() }.cast::<StaticBody>())
+            .expect("ChunkCollisionShape should be a child of a StaticBody");
+        for owner in self.owners.drain(..) {
+            body.remove_shape_owner(owner);
+        }
+        for corners in boxes.read().chunks(2) {
+            let (shape, transform) = build_box_shape(corners[0], corners[1]);
+            let owner = body.create_shape_owner(self.base);
+            body.shape_owner_add_shape(owner, shape);
+            body.shape_owner_set_transform(owner, transform);
+            self.owners.push(owner);
+        }
+    }
 }
 
 #[derive(NativeClass)]
@@ -56,30 +86,68 @@ impl ChunkMeshInstance {
     }
 }
 
-pub struct ChunkNode {
-    body: Ref<StaticBody, Shared>,
+/// The mesh and collision boxes of one section of a chunk.
+struct SectionNode {
     mesh: Option<Instance<ChunkMeshInstance, Shared>>,
     collision: Instance<ChunkCollisionShape, Shared>,
 }
 
-impl ChunkNode {
-    pub fn new(mesh: Option<Instance<ChunkMeshInstance, Unique>>) -> Self {
-        let body = StaticBody::new();
+impl SectionNode {
+    fn new(body: &StaticBody, with_mesh: bool) -> Self {
         let collision = ChunkCollisionShape::new_instance().into_shared();
         body.add_child(&collision, true);
-        let mesh = mesh.map(|m| m.into_shared());
+        let mesh = with_mesh.then(|| ChunkMeshInstance::new_instance().into_shared());
         if let Some(ref mesh) = mesh {
             body.add_child(mesh, true);
         }
+        Self { mesh, collision }
+    }
+
+    fn update_collision(&mut self, collision_data: &ChunkCollisionData) {
+        unsafe { self.collision.assume_safe() }
+            .map_mut(|collision, _base| collision.update(collision_data))
+            .unwrap();
+    }
+
+    fn update_mesh(&mut self, mesh_data: &ChunkMeshData) {
+        if let Some(ref mesh) = self.mesh {
+            unsafe { mesh.assume_safe() }
+                .map_mut(|mesh, _base| {
+                    mesh.update(mesh_data);
+                })
+                .unwrap();
+        }
+    }
+
+    fn set_visible(&self, visible: bool) {
+        if let Some(ref mesh) = self.mesh {
+            unsafe { mesh.assume_safe() }.base().set_visible(visible);
+        }
+    }
+}
+
+/// A chunk in the Godot world, with a separate mesh and set of collision boxes
+/// for each of its sections.
+pub struct ChunkNode {
+    body: Ref<StaticBody, Shared>,
+    sections: Vec<SectionNode>,
+}
+
+impl ChunkNode {
+    /// Creates a chunk node, whose sections only have meshes if `with_mesh` is `true`.
+    pub fn new(with_mesh: bool) -> Self {
+        let body = StaticBody::new();
+        let sections = (0..SECTIONS_PER_CHUNK)
+            .map(|_| SectionNode::new(&body, with_mesh))
+            .collect();
         Self {
             body: body.into_shared(),
-            collision,
-            mesh,
+            sections,
         }
     }
 
     pub fn new_with_mesh() -> Self {
-        Self::new(Some(ChunkMeshInstance::new_instance()))
+        Self::new(true)
     }
 
     pub fn spawn(&mut self, parent: &Node, position: ChunkPos) {
@@ -88,16 +156,21 @@ impl ChunkNode {
         parent.add_child(self.body, true);
     }
 
-    pub fn update(&mut self, mesh_data: &ChunkMeshData) {
-        unsafe { self.collision.assume_safe() }
-            .map_mut(|collision, _base| collision.update(mesh_data))
-            .unwrap();
-        if let Some(ref mesh) = self.mesh {
-            unsafe { mesh.assume_safe() }
-                .map_mut(|mesh, _base| {
-                    mesh.update(mesh_data);
-                })
-                .unwrap();
-        }
+    /// Replaces the mesh of `section` with one built from `mesh_data`.
+    ///
+    /// Does nothing if this chunk was created without meshes.
+    pub fn update_mesh(&mut self, section: usize, mesh_data: &ChunkMeshData) {
+        self.sections[section].update_mesh(mesh_data);
+    }
+
+    /// Shows or hides the mesh of `section`, e.g. when it's culled. Its collision
+    /// boxes are left as they are.
+    pub fn set_section_visible(&self, section: usize, visible: bool) {
+        self.sections[section].set_visible(visible);
+    }
+
+    /// Replaces the collision boxes of `section` with `collision_data`.
+    pub fn update_collision(&mut self, section: usize, collision_data: &ChunkCollisionData) {
+        self.sections[section].update_collision(collision_data);
     }
 }
@@ -1,12 +1,6 @@
-//! Mesh data structs and Godot `ArrayMesh` generation.
+//! Mesh data structs, independent of any engine.
 
-use gdnative::{
-    api::ArrayMesh,
-    core_types::{Color, ColorArray, VariantArray, Vector2, Vector2Array, Vector3, Vector3Array},
-    prelude::Unique,
-};
-
-use crate::{color, vec2, vec3};
+use crate::{light::MAX_LIGHT, texture::TextureLayer};
 
 #[derive(Clone, Copy, Debug)]
 enum Axis {
@@ -16,182 +10,360 @@ enum Axis {
 }
 
 impl Axis {
-    fn uv(axes: [Self; 2], vertex: [isize; 3]) -> [f32; 2] {
+    /// Gets the UV of `vertex`, for a face that is `size` blocks across `axes`.
+    ///
+    /// UVs are measured in blocks, so that the texture repeats across larger faces.
+    fn uv(axes: [Self; 2], vertex: [isize; 3], size: [usize; 2]) -> [f32; 2] {
         let x_idx = axes[0] as usize;
         let z_idx = axes[1] as usize;
-        [vertex[x_idx].abs() as f32, vertex[z_idx].abs() as f32]
+        [
+            (vertex[x_idx].unsigned_abs() * size[0]) as f32,
+            (vertex[z_idx].unsigned_abs() * size[1]) as f32,
+        ]
     }
 }
 
 #[derive(Debug)]
 pub struct Face {
-    pub vertices: [[isize; 3]; 6],
+    /// The four corners of the face, in the order expected by [`TRIANGLES`].
+    pub corners: [[isize; 3]; 4],
     pub normal: [isize; 3],
     uv_use: [Axis; 2],
 }
 
+/// The corners of each triangle in a face, split along the diagonal between corners 1 and 2.
+pub(crate) const TRIANGLES: [usize; 6] = [0, 1, 2, 1, 3, 2];
+/// The corners of each triangle in a face, split along the diagonal between corners 0 and 3.
+const FLIPPED_TRIANGLES: [usize; 6] = [0, 1, 3, 0, 3, 2];
+
+impl Face {
+    /// Returns the axis (0, 1 or 2 for X, Y or Z) that this face points along.
+    pub fn normal_axis(&self) -> usize {
+        self.normal.iter().position(|n| *n != 0).unwrap()
+    }
+    /// Returns the axes that this face's UVs run along.
+    pub fn uv_axes(&self) -> [usize; 2] {
+        [self.uv_use[0] as usize, self.uv_use[1] as usize]
+    }
+    /// Returns the corners of this face, stretched to cover `size` blocks along its
+    /// UV axes, relative to the block it starts at.
+    pub fn scaled_corners(&self, size: [usize; 2]) -> [[isize; 3]; 4] {
+        let mut scale = [1; 3];
+        for (axis, size) in self.uv_axes().into_iter().zip(size) {
+            scale[axis] = size as isize;
+        }
+        self.corners.map(|vertex| {
+            [0, 1, 2].map(|axis| {
+                // Blocks span y-1..y rather than y..y+1.
+                let lower = if axis == 1 { -1 } else { 0 };
+                (vertex[axis] - lower) * scale[axis] + lower
+            })
+        })
+    }
+    /// Returns the offsets of the blocks that can occlude `corner` of this face,
+    /// relative to the block the face belongs to.
+    ///
+    /// These are the two blocks beside the corner, followed by the one diagonal to it,
+    /// all in the layer of blocks the face is facing.
+    pub fn occluders(&self, corner: usize) -> [[isize; 3]; 3] {
+        let vertex = self.corners[corner];
+        let mut sides = [self.normal; 2];
+        let mut side = sides.iter_mut();
+        for (axis, (normal, coordinate)) in self.normal.iter().zip(vertex).enumerate() {
+            if *normal != 0 {
+                continue;
+            }
+            // Blocks span y-1..y rather than y..y+1.
+            let lower = if axis == 1 { -1 } else { 0 };
+            side.next().unwrap()[axis] += if coordinate == lower { -1 } else { 1 };
+        }
+        let diagonal = [0, 1, 2].map(|axis| sides[0][axis] + sides[1][axis] - self.normal[axis]);
+        [sides[0], sides[1], diagonal]
+    }
+}
+
 pub const FACES: [Face; 6] = [
     // Top, Y+
     Face {
-        vertices: [
-            [0, 0, 0],
-            [1, 0, 0],
-            [0, 0, 1],
-            [1, 0, 0],
-            [1, 0, 1],
-            [0, 0, 1],
-        ],
+        corners: [[0, 0, 0], [1, 0, 0], [0, 0, 1], [1, 0, 1]],
         normal: [0, 1, 0],
         uv_use: [Axis::X, Axis::Z],
     },
     // Bottom, Y-
     Face {
-        vertices: [
-            [0, -1, 1],
-            [1, -1, 1],
-            [0, -1, 0],
-            [1, -1, 1],
-            [1, -1, 0],
-            [0, -1, 0],
-        ],
+        corners: [[0, -1, 1], [1, -1, 1], [0, -1, 0], [1, -1, 0]],
         normal: [0, -1, 0],
         uv_use: [Axis::X, Axis::Z],
     },
     // Right, X+
     Face {
-        vertices: [
-            [1, 0, 1],
-            [1, 0, 0],
-            [1, -1, 1],
-            [1, -1, 0],
-            [1, -1, 1],
-            [1, 0, 0],
-        ],
+        corners: [[1, 0, 1], [1, 0, 0], [1, -1, 1], [1, -1, 0]],
         normal: [1, 0, 0],
         uv_use: [Axis::Z, Axis::Y],
     },
     // Left, X-
     Face {
-        vertices: [
-            [0, 0, 0],
-            [0, 0, 1],
-            [0, -1, 0],
-            [0, 0, 1],
-            [0, -1, 1],
-            [0, -1, 0],
-        ],
+        corners: [[0, 0, 0], [0, 0, 1], [0, -1, 0], [0, -1, 1]],
         normal: [-1, 0, 0],
         uv_use: [Axis::Z, Axis::Y],
     },
     // Front, Z+
     Face {
-        vertices: [
-            [0, -1, 1],
-            [0, 0, 1],
-            [1, 0, 1],
-            [1, 0, 1],
-            [1, -1, 1],
-            [0, -1, 1],
-        ],
+        corners: [[0, 0, 1], [1, 0, 1], [0, -1, 1], [1, -1, 1]],
         normal: [0, 0, 1],
         uv_use: [Axis::X, Axis::Y],
     },
     // Back, Z-
     Face {
-        vertices: [
-            [1, -1, 0],
-            [1, 0, 0],
-            [0, 0, 0],
-            [0, 0, 0],
-            [0, -1, 0],
-            [1, -1, 0],
-        ],
+        corners: [[1, 0, 0], [0, 0, 0], [1, -1, 0], [0, -1, 0]],
         normal: [0, 0, -1],
         uv_use: [Axis::X, Axis::Y],
     },
 ];
 
+/// How bright a corner is for each ambient occlusion level, from fully occluded (0)
+/// to not occluded at all (3).
+const OCCLUSION_BRIGHTNESS: [f32; 4] = [0.45, 0.65, 0.82, 1.0];
+
+/// Calculates the ambient occlusion level of a corner from whether the blocks
+/// returned by [`Face::occluders`] are solid.
+///
+/// A corner between two solid blocks is fully occluded, no matter what is diagonal to it.
+pub fn corner_occlusion(side1: bool, side2: bool, diagonal: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - side1 as u8 - side2 as u8 - diagonal as u8
+    }
+}
+
+/// Converts a light level (`0..=MAX_LIGHT`) into a vertex color brightness.
+///
+/// Every level is 80% as bright as the one above it, which looks much more
+/// natural than a linear falloff.
+pub(crate) fn light_brightness(light: u8) -> u8 {
+    let exponent = MAX_LIGHT.saturating_sub(light) as i32;
+    (255.0 * 0.8f32.powi(exponent)) as u8
+}
+
+/// How a face is shaded.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Shading {
+    /// The light level of the block the face is facing.
+    pub light: u8,
+    /// The ambient occlusion level of each of the face's corners.
+    pub occlusion: [u8; 4],
+    /// The colour each of the face's corners is multiplied by, white if it isn't tinted.
+    pub tint: [[u8; 3]; 4],
+}
+
+impl Shading {
+    /// Shading for a face with nothing around it, lit by `light`.
+    pub fn unoccluded(light: u8) -> Self {
+        Self {
+            light,
+            occlusion: [3; 4],
+            tint: [[255; 3]; 4],
+        }
+    }
+}
+
+/// The size of a vertex once uploaded to the GPU, in bytes.
+///
+/// Positions are full floats, while everything else is compressed by Godot:
+/// colors are 8-bit (4 bytes), and both sets of UVs are half floats (4 bytes each).
+/// Normals aren't uploaded at all. Every face is flat, so the block shaders work
+/// them out from how the position changes across it instead.
+pub const UPLOADED_VERTEX_SIZE: usize = 12 + 4 + 4 + 4;
+/// The size a vertex would be if its normal was uploaded too, as an octahedral
+/// normal (4 bytes).
+const VERTEX_SIZE_WITH_NORMAL: usize = UPLOADED_VERTEX_SIZE + 4;
+
 /// Mesh data, like vertices, normals, and UVs.
+///
+/// Every quad has four vertices of its own, which are drawn as two triangles
+/// through `indices`.
+#[derive(Default)]
 pub struct MeshData {
-    pub vertices: Vec<[isize; 3]>,
-    pub normals: Vec<[isize; 3]>,
+    pub vertices: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
     pub colors: Vec<[u8; 4]>,
+    /// UVs, measured in blocks.
     pub uvs: Vec<[f32; 2]>,
+    /// The texture array layer to use, and how many times to turn the texture
+    /// 90 degrees clockwise.
+    pub uv2s: Vec<[f32; 2]>,
+    /// The vertices of each triangle, three at a time.
+    pub indices: Vec<u32>,
 }
 
 impl MeshData {
-    pub fn new() -> Self {
-        // This could be replaced with deriving Default
-        Self {
-            vertices: Vec::new(),
-            normals: Vec::new(),
-            uvs: Vec::new(),
-            colors: Vec::new(),
-        }
+    /// Returns how many quads this mesh is made of.
+    pub fn quad_count(&self) -> usize {
+        self.indices.len() / 6
     }
-    /// Adds a `Face` at `position`.
-    pub fn add_face(&mut self, face: &Face, position: [isize; 3]) {
-        for vertex in face.vertices {
-            self.normals.push(face.normal);
-            self.vertices.push([
-                position[0] + vertex[0],
-                position[1] + vertex[1],
-                position[2] + vertex[2],
-            ]);
-            let uv = Axis::uv(face.uv_use, vertex);
-            // TODO: This is tied to our specific texture system,
-            //       making it more general may be a good idea.
-            let face_offset = match face.normal[1] {
-                1 => 0.0,
-                0 => 1.0,
-                -1 => 2.0,
-                _ => unreachable!(),
-            };
-            let uv = [(face_offset + uv[0]) * 16.0, uv[1] * 16.0];
-            let uv = [uv[0] / 48.0, uv[1] / 16.0];
+    /// Returns how many bytes this mesh takes up once uploaded to the GPU.
+    ///
+    /// Indices are 16-bit, unless there are too many vertices for that.
+    pub fn uploaded_size(&self) -> usize {
+        let index_size = if self.vertices.len() <= u16::MAX as usize + 1 {
+            2
+        } else {
+            4
+        };
+        self.vertices.len() * UPLOADED_VERTEX_SIZE + self.indices.len() * index_size
+    }
+    /// Returns how many bytes this mesh would take up on the GPU if it wasn't
+    /// indexed, i.e. if every triangle had its own three vertices, and each of
+    /// them had its normal uploaded too.
+    pub fn unindexed_uploaded_size(&self) -> usize {
+        self.indices.len() * VERTEX_SIZE_WITH_NORMAL
+    }
+    /// Adds a `Face` at `position`, stretched to cover `size` blocks along its UV axes,
+    /// textured with `texture`, and shaded according to `shading`.
+    ///
+    /// The face is split into triangles along whichever diagonal keeps the occlusion
+    /// symmetrical, so that it doesn't look different depending on the face's orientation.
+    pub fn add_face(
+        &mut self,
+        face: &Face,
+        position: [isize; 3],
+        size: [usize; 2],
+        texture: TextureLayer,
+        shading: Shading,
+    ) {
+        let brightness = light_brightness(shading.light) as f32;
+        let shades = shading
+            .occlusion
+            .map(|level| (brightness * OCCLUSION_BRIGHTNESS[level as usize]) as u8);
+        let corners = face
+            .scaled_corners(size)
+            .map(|vertex| [0, 1, 2].map(|axis| (position[axis] + vertex[axis]) as f32));
+        let uvs = face
+            .corners
+            .map(|vertex| Axis::uv(face.uv_use, vertex, size));
+        self.add_quad(
+            corners,
+            face.normal.map(|n| n as f32),
+            uvs,
+            texture,
+            shades,
+            shading.tint,
+        );
+    }
+    /// Adds a quad with the given `corners`, in the same order as a `Face`'s corners.
+    ///
+    /// Each corner is shaded by its entry in `shades`, multiplied by its entry in `tints`.
+    /// The quad is split into triangles along the diagonal between its two brightest
+    /// corners if they're opposite each other, which keeps the shading symmetrical.
+    pub fn add_quad(
+        &mut self,
+        corners: [[f32; 3]; 4],
+        normal: [f32; 3],
+        uvs: [[f32; 2]; 4],
+        texture: TextureLayer,
+        shades: [u8; 4],
+        tints: [[u8; 3]; 4],
+    ) {
+        let [s0, s1, s2, s3] = shades.map(u16::from);
+        let triangles = if s0 + s3 > s1 + s2 {
+            FLIPPED_TRIANGLES
+        } else {
+            TRIANGLES
+        };
+        let first = self.vertices.len() as u32;
+        self.indices
+            .extend(triangles.map(|corner| first + corner as u32));
+        for (((corner, uv), shade), tint) in corners.into_iter().zip(uvs).zip(shades).zip(tints) {
+            self.normals.push(normal);
+            self.vertices.push(corner);
             self.uvs.push(uv);
-
-            // TODO: should be based on the block this face is facing, not the current block position
-            self.colors.push([255, 255, 255, 255]);
+            self.uv2s
+                .push([texture.layer as f32, texture.rotation as f32]);
+            let [r, g, b] = tint.map(|channel| (shade as u16 * channel as u16 / 255) as u8);
+            self.colors.push([r, g, b, 255]);
         }
     }
-    /// Converts this `MeshData` into a `VariantArray` for use with
-    /// Godot's `ArrayMesh` and its associated concepts.
-    pub fn to_gd_array(&self) -> VariantArray<Unique> {
-        let gdarray = VariantArray::new();
-        gdarray.resize(ArrayMesh::ARRAY_MAX as i32);
-        gdarray.set(
-            ArrayMesh::ARRAY_VERTEX as i32,
-            Vector3Array::from_iter(self.vertices.iter().map(|v| vec3!(v))),
-        );
-        gdarray.set(
-            ArrayMesh::ARRAY_NORMAL as i32,
-            Vector3Array::from_iter(self.normals.iter().map(|v| vec3!(v))),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corner_occlusion() {
+        assert_eq!(corner_occlusion(false, false, false), 3);
+        assert_eq!(corner_occlusion(false, false, true), 2);
+        assert_eq!(corner_occlusion(true, false, true), 1);
+        assert_eq!(corner_occlusion(true, true, false), 0);
+    }
+
+    #[test]
+    fn test_add_face() {
+        let texture = TextureLayer {
+            layer: 3,
+            rotation: 1,
+        };
+        let mut mesh_data = MeshData::default();
+        // The top face of the block at (1, 2, 3), stretched 2 blocks along X.
+        let shading = Shading::unoccluded(MAX_LIGHT);
+        mesh_data.add_face(&FACES[0], [1, 2, 3], [2, 1], texture, shading);
+        assert_eq!(mesh_data.vertices.len(), 4);
+        assert_eq!(mesh_data.indices, vec![0, 1, 2, 1, 3, 2]);
+        assert_eq!(mesh_data.quad_count(), 1);
+        assert!(mesh_data
+            .vertices
+            .iter()
+            .all(|[x, y, z]| (1.0..=3.0).contains(x) && *y == 2.0 && (3.0..=4.0).contains(z)));
+        assert!(mesh_data
+            .normals
+            .iter()
+            .all(|normal| *normal == [0.0, 1.0, 0.0]));
+        assert!(mesh_data.uv2s.iter().all(|uv2| *uv2 == [3.0, 1.0]));
+        assert!(mesh_data.colors.iter().all(|color| *color == [255; 4]));
+
+        // Occlusion that would be lopsided along the usual diagonal flips the triangles.
+        let mut flipped = MeshData::default();
+        flipped.add_face(
+            &FACES[0],
+            [0, 0, 0],
+            [1, 1],
+            texture,
+            Shading {
+                occlusion: [3, 0, 0, 3],
+                ..shading
+            },
         );
-        gdarray.set(
-            ArrayMesh::ARRAY_TEX_UV as i32,
-            Vector2Array::from_iter(self.uvs.iter().map(|v| vec2!(v))),
+        assert_eq!(flipped.indices, vec![0, 1, 3, 0, 3, 2]);
+
+        // Tints are multiplied into the shade of every corner.
+        let mut tinted = MeshData::default();
+        tinted.add_face(
+            &FACES[0],
+            [0, 0, 0],
+            [1, 1],
+            texture,
+            Shading {
+                light: MAX_LIGHT - 1,
+                tint: [[255, 128, 0], [255; 3], [255; 3], [0; 3]],
+                ..shading
+            },
         );
-        gdarray.set(
-            ArrayMesh::ARRAY_COLOR as i32,
-            ColorArray::from_iter(self.colors.iter().map(|v| color!(v))),
+        assert_eq!(
+            tinted.colors,
+            vec![
+                [204, 102, 0, 255],
+                [204, 204, 204, 255],
+                [204, 204, 204, 255],
+                [0, 0, 0, 255]
+            ]
         );
-        gdarray
-    }
-}
 
-/// Adds `array_data` to `mesh` as a new surface.
-///
-/// Returns the new surface's "surface index".
-pub fn add_surface(array_data: VariantArray<gdnative::prelude::Shared>, mesh: &ArrayMesh) -> i64 {
-    let surf_idx = mesh.get_surface_count();
-    mesh.add_surface_from_arrays(
-        gdnative::api::Mesh::PRIMITIVE_TRIANGLES,
-        array_data,
-        VariantArray::new().into_shared(),
-        2194432,
-    );
-    surf_idx
+        // A second quad's indices point at its own vertices.
+        mesh_data.add_face(&FACES[1], [1, 2, 3], [1, 1], texture, shading);
+        assert_eq!(mesh_data.indices[6..], [4, 5, 6, 5, 7, 6]);
+        // Each quad takes 4 vertices and 6 16-bit indices, rather than 6 vertices.
+        assert_eq!(mesh_data.uploaded_size(), 2 * (4 * 24 + 6 * 2));
+        assert_eq!(mesh_data.unindexed_uploaded_size(), 2 * 6 * 28);
+    }
 }
-
-// TODO: tests
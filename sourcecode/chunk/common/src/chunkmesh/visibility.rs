@@ -0,0 +1,273 @@
+//! Cave culling, which hides the sections that are walled off from the camera.
+//!
+//! Every section records which of its six faces can see each other through the
+//! see-through blocks inside it. Finding the visible sections is then a search that
+//! starts at the camera, and only leaves each section through a face that's connected
+//! to the one it came in through, without ever turning back towards the camera.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    block::BLOCK_MANAGER,
+    chunk::ChunkData,
+    constants::{SECTIONS_PER_CHUNK, SECTION_SIZE_Y},
+    prelude::*,
+    texture::BlockFace,
+};
+
+/// Roughly how far the corners of a section are from its centre, in blocks.
+const SECTION_RADIUS: f32 = 28.0;
+
+/// Returns `true` if `block_id` can be seen through.
+fn is_see_through(block_id: BlockID) -> bool {
+    block_id == 0 || BLOCK_MANAGER.get().transparent_blocks.contains(&block_id)
+}
+
+/// Which faces of a section can see each other through the blocks inside it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct SectionVisibility {
+    /// A bit for every pair of faces, set if they're connected.
+    connections: u64,
+}
+
+impl SectionVisibility {
+    /// Every face can see every other, like in a section full of air.
+    pub const ALL: Self = Self {
+        connections: (1 << 36) - 1,
+    };
+    /// No face can see any other, like in a section full of stone.
+    pub const NONE: Self = Self { connections: 0 };
+
+    fn bit(a: BlockFace, b: BlockFace) -> u64 {
+        1 << (a as usize * 6 + b as usize)
+    }
+
+    /// Returns `true` if something entering the section through face `a`
+    /// could be seen through face `b`.
+    pub fn connected(&self, a: BlockFace, b: BlockFace) -> bool {
+        self.connections & Self::bit(a, b) != 0
+    }
+
+    /// Works out which faces of the section of `chunk_data` at height `section` are
+    /// connected, by flood filling each group of see-through blocks in it and
+    /// connecting every face that group touches.
+    pub fn new_from_section(chunk_data: &ChunkData, section: usize) -> Self {
+        let [width, height, depth] = [CHUNK_SIZE_X, SECTION_SIZE_Y, CHUNK_SIZE_Z];
+        let bottom = section * SECTION_SIZE_Y;
+        let index = |[x, y, z]: [usize; 3]| (x * height + y) * depth + z;
+        // The see-through blocks that haven't been filled yet.
+        let mut open = vec![false; width * height * depth];
+        for x in 0..width {
+            for y in 0..height {
+                for z in 0..depth {
+                    let position = LocalBlockPos::new(x, bottom + y, z, chunk_data.position);
+                    open[index([x, y, z])] = is_see_through(chunk_data.get(position));
+                }
+            }
+        }
+        let mut visibility = Self::NONE;
+        let mut stack = Vec::new();
+        for x in 0..width {
+            for y in 0..height {
+                for z in 0..depth {
+                    if !open[index([x, y, z])] {
+                        continue;
+                    }
+                    open[index([x, y, z])] = false;
+                    stack.push([x, y, z]);
+                    let mut touched = Vec::new();
+                    while let Some(block) = stack.pop() {
+                        for face in BlockFace::ALL {
+                            let normal = face.normal();
+                            let mut next = [0; 3];
+                            let mut on_edge = false;
+                            for axis in 0..3 {
+                                let size = [width, height, depth][axis] as isize;
+                                let coordinate = block[axis] as isize + normal[axis];
+                                on_edge |= !(0..size).contains(&coordinate);
+                                next[axis] = coordinate as usize;
+                            }
+                            if on_edge {
+                                if !touched.contains(&face) {
+                                    touched.push(face);
+                                }
+                            } else if open[index(next)] {
+                                open[index(next)] = false;
+                                stack.push(next);
+                            }
+                        }
+                    }
+                    for a in &touched {
+                        for b in &touched {
+                            visibility.connections |= Self::bit(*a, *b);
+                        }
+                    }
+                }
+            }
+        }
+        visibility
+    }
+}
+
+/// Returns the section that `position` is in, or `None` if it's above or below the world.
+///
+/// `position` is in the same space as chunk meshes, so blocks span y-1..y.
+pub fn section_at(position: [f32; 3]) -> Option<SectionPos> {
+    let [x, y, z] = position.map(|n| n.floor() as isize);
+    let block = GlobalBlockPos::new(x, y + 1, z);
+    if !(0..CHUNK_SIZE_Y as isize).contains(&block.y) {
+        return None;
+    }
+    Some(SectionPos::new(
+        block.chunk(),
+        block.y as usize / SECTION_SIZE_Y,
+    ))
+}
+
+/// Returns the centre of `section`, in the same space as chunk meshes.
+fn center(section: SectionPos) -> [f32; 3] {
+    let origin = section.chunk.origin();
+    [
+        origin.x as f32 + CHUNK_SIZE_X as f32 / 2.0,
+        (section.y * SECTION_SIZE_Y) as f32 + SECTION_SIZE_Y as f32 / 2.0 - 1.0,
+        origin.z as f32 + CHUNK_SIZE_Z as f32 / 2.0,
+    ]
+}
+
+/// Returns the section next to `section` through `face`, or `None` if that's
+/// above or below the world.
+fn neighbour(section: SectionPos, face: BlockFace) -> Option<SectionPos> {
+    let [x, y, z] = face.normal();
+    let y = section.y as isize + y;
+    if !(0..SECTIONS_PER_CHUNK as isize).contains(&y) {
+        return None;
+    }
+    let chunk = ChunkPos::new(section.chunk.x + x, section.chunk.z + z);
+    Some(SectionPos::new(chunk, y as usize))
+}
+
+/// Finds the sections that can be seen from `camera` while looking along `direction`.
+///
+/// `visibility` returns how a section can be seen through, or `None` if it isn't
+/// loaded. Nothing is seen through unloaded sections, and sections that are entirely
+/// behind the camera are skipped.
+///
+/// Returns `None` if the camera isn't in a loaded section, since nothing can be
+/// ruled out then.
+pub fn visible_sections(
+    camera: [f32; 3],
+    direction: [f32; 3],
+    visibility: impl Fn(SectionPos) -> Option<SectionVisibility>,
+) -> Option<HashSet<SectionPos>> {
+    let length = direction.iter().map(|n| n * n).sum::<f32>().sqrt();
+    let direction = direction.map(|n| n / length.max(f32::EPSILON));
+    let start = section_at(camera)?;
+    let mut visible = HashSet::from([start]);
+    // Each section is queued with how it can be seen through, the face it was
+    // entered through, and the directions taken to reach it as a bit per face.
+    let mut queue = VecDeque::from([(start, visibility(start)?, None, 0u8)]);
+    while let Some((section, section_visibility, entered, travelled)) = queue.pop_front() {
+        for face in BlockFace::ALL {
+            if travelled & (1 << face.opposite() as u8) != 0 {
+                continue;
+            }
+            if let Some(entered) = entered {
+                if !section_visibility.connected(entered, face) {
+                    continue;
+                }
+            }
+            let next = match neighbour(section, face) {
+                Some(next) if !visible.contains(&next) => next,
+                _ => continue,
+            };
+            let next_visibility = match visibility(next) {
+                Some(next_visibility) => next_visibility,
+                None => continue,
+            };
+            let next_center = center(next);
+            let distance_ahead: f32 = (0..3)
+                .map(|axis| (next_center[axis] - camera[axis]) * direction[axis])
+                .sum();
+            if distance_ahead < -SECTION_RADIUS {
+                continue;
+            }
+            visible.insert(next);
+            queue.push_back((
+                next,
+                next_visibility,
+                Some(face.opposite()),
+                travelled | 1 << face as u8,
+            ));
+        }
+    }
+    Some(visible)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_visibility() {
+        let mut chunk_data = ChunkData::new(ChunkPos::new(0, 0));
+        assert_eq!(
+            SectionVisibility::new_from_section(&chunk_data, 2),
+            SectionVisibility::ALL
+        );
+
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        chunk_data.terrain.fill(stone);
+        assert_eq!(
+            SectionVisibility::new_from_section(&chunk_data, 2),
+            SectionVisibility::NONE
+        );
+
+        // A shaft straight down through the section, and a tunnel running
+        // from the north to the east without touching it.
+        for y in 64..96 {
+            chunk_data.set(LocalBlockPos::new(5, y, 5, chunk_data.position), 0);
+        }
+        for z in 0..20 {
+            chunk_data.set(LocalBlockPos::new(20, 70, z, chunk_data.position), 0);
+        }
+        for x in 20..CHUNK_SIZE_X {
+            chunk_data.set(LocalBlockPos::new(x, 70, 19, chunk_data.position), 0);
+        }
+        let visibility = SectionVisibility::new_from_section(&chunk_data, 2);
+        assert!(visibility.connected(BlockFace::Top, BlockFace::Bottom));
+        assert!(visibility.connected(BlockFace::North, BlockFace::East));
+        assert!(visibility.connected(BlockFace::East, BlockFace::North));
+        assert!(!visibility.connected(BlockFace::Top, BlockFace::North));
+        assert!(!visibility.connected(BlockFace::North, BlockFace::South));
+        assert!(!visibility.connected(BlockFace::West, BlockFace::West));
+    }
+
+    #[test]
+    fn test_visible_sections() {
+        // A row of chunks along X, with a wall that can't be seen through at x=1.
+        let visibility = |section: SectionPos| match (section.chunk.x, section.chunk.z) {
+            (1, 0) => Some(SectionVisibility::NONE),
+            (-3..=3, 0) => Some(SectionVisibility::ALL),
+            _ => None,
+        };
+        let camera = [16.0, 80.0, 16.0];
+        let visible = visible_sections(camera, [1.0, 0.0, 0.0], visibility).unwrap();
+        let at = |x, y| SectionPos::new(ChunkPos::new(x, 0), y);
+        assert!(visible.contains(&at(0, 2)));
+        assert!(visible.contains(&at(0, 15)));
+        // The wall itself is visible, but not what's behind it.
+        assert!(visible.contains(&at(1, 2)));
+        assert!(!visible.contains(&at(2, 2)));
+        // Nor what's behind the camera.
+        assert!(!visible.contains(&at(-2, 2)));
+
+        // Turning around shows what was behind the camera.
+        let visible = visible_sections(camera, [-1.0, 0.0, 0.0], visibility).unwrap();
+        assert!(visible.contains(&at(-3, 2)));
+        assert!(!visible.contains(&at(2, 2)));
+
+        // Outside of the loaded chunks, nothing can be ruled out.
+        assert!(visible_sections([200.0, 80.0, 16.0], [1.0, 0.0, 0.0], visibility).is_none());
+        assert!(visible_sections([16.0, 600.0, 16.0], [1.0, 0.0, 0.0], visibility).is_none());
+    }
+}
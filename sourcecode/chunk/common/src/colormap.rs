@@ -0,0 +1,226 @@
+//! Colour maps, which tint the faces of blocks like grass and leaves.
+//!
+//! Colour maps are defined in `colormaps.json`, and pick a colour from the climate of
+//! the column a block is in, the current season, or both. Blocks use them by naming
+//! one in the `tint` field of their entry in `blocks.json`, and the chosen colour is
+//! multiplied into the vertex colours of their tinted faces.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    block::{BlockID, BlockManager, BLOCK_MANAGER},
+    reloadable::Reloadable,
+    texture::BlockFace,
+};
+
+/// The file next to a module's `blocks.json` that holds its colour maps.
+pub const COLORMAPS_FILE: &str = "colormaps.json";
+
+/// The colour maps of the embedded [`DEFAULT_MODULE`](crate::block::DEFAULT_MODULE).
+pub(crate) const COLORMAPS_JSON: &str = include_str!("../../../VoxelGame/assets/colormaps.json"); // HARDCODED
+
+/// The number of seasons in a year, see [`set_season`].
+pub const SEASONS: f32 = 4.0;
+
+/// The current season, as the bits of an `f32`. Starts in the middle of summer (1.0).
+static SEASON: AtomicU32 = AtomicU32::new(0x3F80_0000);
+
+/// Sets the current season, from 0 (spring) through 1 (summer) and 2 (autumn)
+/// to 3 (winter). Fractional values blend between neighbouring seasons, and
+/// the year wraps around, so 3.5 is halfway between winter and spring.
+///
+/// Meshes built afterwards use the new season's colours.
+pub fn set_season(season: f32) {
+    SEASON.store(season.rem_euclid(SEASONS).to_bits(), Ordering::Relaxed);
+}
+
+/// Returns the current season, see [`set_season`].
+pub fn season() -> f32 {
+    f32::from_bits(SEASON.load(Ordering::Relaxed))
+}
+
+/// Which faces of a block are tinted, and by which colour map.
+#[derive(Debug, Deserialize)]
+pub struct BlockTint {
+    /// The name of the colour map in `colormaps.json`.
+    pub colormap: String,
+    /// The faces that are tinted. Defaults to all of them.
+    #[serde(default = "all_faces")]
+    pub faces: Vec<BlockFace>,
+}
+
+fn all_faces() -> Vec<BlockFace> {
+    BlockFace::ALL.to_vec()
+}
+
+/// Linearly interpolates between the colours `a` and `b`.
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|channel| a[channel] + (b[channel] - a[channel]) * t)
+}
+
+/// Returns the colour at `position` along `colors`, blending between the two
+/// colours on either side of it.
+fn sample(colors: &[[u8; 3]], position: f32) -> [f32; 3] {
+    let last = colors.len() - 1;
+    let position = position.clamp(0.0, last as f32);
+    let index = (position as usize).min(last.saturating_sub(1));
+    let next = (index + 1).min(last);
+    let [a, b] = [colors[index], colors[next]].map(|color| color.map(f32::from));
+    lerp(a, b, position - index as f32)
+}
+
+/// A colour map from `colormaps.json`.
+///
+/// Colour maps may have colours for each climate, each season, or both,
+/// in which case the two are multiplied together.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Colormap {
+    /// Rows of colours from the coldest to the hottest climate, with each row going
+    /// from the driest to the wettest. Climates between them are blended bilinearly.
+    climate: Option<Vec<Vec<[u8; 3]>>>,
+    /// The colours of spring, summer, autumn and winter.
+    seasons: Option<[[u8; 3]; 4]>,
+}
+
+impl Colormap {
+    /// Returns the colour for `climate`, the temperature and humidity of a column
+    /// from 0 to 255, during `season`.
+    pub fn color(&self, climate: [u8; 2], season: f32) -> [u8; 3] {
+        let mut color = [255.0; 3];
+        if let Some(rows) = &self.climate {
+            let [temperature, humidity] = climate.map(|n| n as f32 / 255.0);
+            let row_position = temperature * (rows.len() - 1) as f32;
+            let row = (row_position as usize).min(rows.len().saturating_sub(2));
+            let next_row = (row + 1).min(rows.len() - 1);
+            let [a, b] = [&rows[row], &rows[next_row]]
+                .map(|colors| sample(colors, humidity * (colors.len() - 1) as f32));
+            let blended = lerp(a, b, (row_position - row as f32).clamp(0.0, 1.0));
+            color = [0, 1, 2].map(|channel| color[channel] * blended[channel] / 255.0);
+        }
+        if let Some(seasons) = &self.seasons {
+            let season = season.rem_euclid(SEASONS);
+            let index = season as usize % seasons.len();
+            let [a, b] = [seasons[index], seasons[(index + 1) % seasons.len()]]
+                .map(|color| color.map(f32::from));
+            let blended = lerp(a, b, season - index as f32);
+            color = [0, 1, 2].map(|channel| color[channel] * blended[channel] / 255.0);
+        }
+        color.map(|channel| channel.round() as u8)
+    }
+}
+
+#[derive(Deserialize)]
+struct ColormapsFile {
+    colormaps: HashMap<String, Colormap>,
+}
+
+/// Parses the contents of a `colormaps.json` file.
+pub(crate) fn parse_colormaps(
+    bytes: &[u8],
+) -> Result<HashMap<String, Colormap>, serde_json::Error> {
+    let file: ColormapsFile = serde_json::from_slice(bytes)?;
+    Ok(file.colormaps)
+}
+
+/// The colour maps used by each tinted block.
+pub struct ColormapManager {
+    /// The colour map of each tinted block, along with the faces it applies to.
+    block_tints: HashMap<BlockID, (Colormap, Vec<BlockFace>)>,
+}
+
+impl ColormapManager {
+    /// Picks the colour maps of the embedded blocks.
+    fn new() -> Self {
+        let colormaps = parse_colormaps(COLORMAPS_JSON.as_bytes()).unwrap();
+        Self::from_definitions(&BLOCK_MANAGER.get(), &colormaps)
+    }
+
+    /// Picks the colour map of every tinted block in `blocks` out of `colormaps`.
+    pub(crate) fn from_definitions(
+        blocks: &BlockManager,
+        colormaps: &HashMap<String, Colormap>,
+    ) -> Self {
+        let block_tints = blocks
+            .blocks()
+            .filter_map(|block| {
+                let tint = block.tint.as_ref()?;
+                let colormap = colormaps.get(&tint.colormap).unwrap_or_else(|| {
+                    panic!("Block {} uses missing colormap {}", block.id, tint.colormap)
+                });
+                Some((block.id, (colormap.clone(), tint.faces.clone())))
+            })
+            .collect();
+        Self { block_tints }
+    }
+
+    /// Returns the colour map tinting the `face` of `block_id`, or `None` if that
+    /// face isn't tinted.
+    pub fn colormap(&self, block_id: BlockID, face: BlockFace) -> Option<&Colormap> {
+        let (colormap, faces) = self.block_tints.get(&block_id)?;
+        faces.contains(&face).then_some(colormap)
+    }
+}
+
+pub static COLORMAP_MANAGER: Reloadable<ColormapManager> = Reloadable::new(ColormapManager::new);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_climate_colormap() {
+        let colormap: Colormap = serde_json::from_str(
+            r#"{ "climate": [
+                [[0, 0, 0], [0, 0, 200]],
+                [[200, 0, 0], [200, 0, 200]]
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(colormap.color([0, 0], 1.0), [0, 0, 0]);
+        assert_eq!(colormap.color([255, 0], 1.0), [200, 0, 0]);
+        assert_eq!(colormap.color([255, 255], 1.0), [200, 0, 200]);
+        // Anywhere in between blends all four corners.
+        assert_eq!(colormap.color([51, 204], 1.0), [40, 0, 160]);
+    }
+
+    #[test]
+    fn test_season_colormap() {
+        let colormap: Colormap = serde_json::from_str(
+            r#"{ "seasons": [[0, 0, 0], [255, 255, 255], [200, 100, 0], [100, 100, 100]] }"#,
+        )
+        .unwrap();
+        assert_eq!(colormap.color([0, 0], 1.0), [255, 255, 255]);
+        assert_eq!(colormap.color([0, 0], 2.5), [150, 100, 50]);
+        // Winter blends back into spring.
+        assert_eq!(colormap.color([0, 0], 3.5), [50, 50, 50]);
+        assert_eq!(colormap.color([0, 0], -0.5), [50, 50, 50]);
+    }
+
+    #[test]
+    fn test_block_tints() {
+        let grass = BLOCK_MANAGER.get().block("grass").unwrap().id;
+        assert!(COLORMAP_MANAGER
+            .get()
+            .colormap(grass, BlockFace::Top)
+            .is_some());
+        assert!(COLORMAP_MANAGER
+            .get()
+            .colormap(grass, BlockFace::North)
+            .is_none());
+        let leaves = BLOCK_MANAGER.get().block("chestnut_leaves").unwrap().id;
+        assert!(COLORMAP_MANAGER
+            .get()
+            .colormap(leaves, BlockFace::North)
+            .is_some());
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        assert!(COLORMAP_MANAGER
+            .get()
+            .colormap(stone, BlockFace::Top)
+            .is_none());
+    }
+}
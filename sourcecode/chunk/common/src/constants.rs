@@ -3,7 +3,14 @@ use crate::block::BlockID;
 pub const CHUNK_SIZE_X: usize = 32;
 pub const CHUNK_SIZE_Y: usize = 512;
 pub const CHUNK_SIZE_Z: usize = 32;
+/// The height of the sections that chunk meshes are split into.
+pub const SECTION_SIZE_Y: usize = 32;
+/// How many sections are stacked on top of each other in a chunk.
+pub const SECTIONS_PER_CHUNK: usize = CHUNK_SIZE_Y / SECTION_SIZE_Y;
 
 pub type TerrainData = ndarray::Array3<BlockID>;
-// TODO: Use a u8
-pub type LightLevelData = ndarray::Array3<u16>;
+/// Packed light levels, with sky light in the upper 4 bits of each value
+/// and block light in the lower 4 bits.
+pub type LightLevelData = ndarray::Array3<u8>;
+/// The temperature and humidity of each column, from 0 to 255.
+pub type ClimateData = ndarray::Array2<[u8; 2]>;
@@ -33,3 +33,85 @@ impl std::error::Error for OffsetError {
         None
     }
 }
+
+/// Errors from loading block definitions at runtime.
+#[derive(Debug)]
+pub enum BlockLoadError {
+    /// The file couldn't be read.
+    Io(std::io::Error),
+    /// The file isn't valid `blocks.json`.
+    Parse(serde_json::Error),
+    /// A block uses a model or colour map that doesn't exist.
+    MissingAsset { block: String, asset: String },
+    /// One of a block's face textures can't be used, for the given reason.
+    InvalidTexture {
+        block: String,
+        texture: String,
+        reason: &'static str,
+    },
+    /// A module or block name is empty or contains a `:`.
+    InvalidName(String),
+    /// Two of the files are for the same module.
+    DuplicateModule(String),
+    /// Two modules have a model or colour map with the same name.
+    DuplicateAsset(String),
+    /// There are more blocks than fit in a `BlockID`.
+    TooManyBlocks,
+}
+
+impl std::fmt::Display for BlockLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockLoadError::Io(error) => write!(f, "couldn't read block definitions: {}", error),
+            BlockLoadError::Parse(error) => write!(f, "invalid block definitions: {}", error),
+            BlockLoadError::MissingAsset { block, asset } => {
+                write!(
+                    f,
+                    "block {} uses missing model or colormap {}",
+                    block, asset
+                )
+            }
+            BlockLoadError::InvalidTexture {
+                block,
+                texture,
+                reason,
+            } => write!(
+                f,
+                "block {} can't use texture {}: {}",
+                block, texture, reason
+            ),
+            BlockLoadError::InvalidName(name) => {
+                write!(f, "invalid module or block name {:?}", name)
+            }
+            BlockLoadError::DuplicateModule(module) => {
+                write!(f, "module {} is loaded more than once", module)
+            }
+            BlockLoadError::DuplicateAsset(name) => {
+                write!(f, "model or colormap {} is defined more than once", name)
+            }
+            BlockLoadError::TooManyBlocks => write!(f, "too many blocks"),
+        }
+    }
+}
+
+impl std::error::Error for BlockLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlockLoadError::Io(error) => Some(error),
+            BlockLoadError::Parse(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BlockLoadError {
+    fn from(error: std::io::Error) -> Self {
+        BlockLoadError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for BlockLoadError {
+    fn from(error: serde_json::Error) -> Self {
+        BlockLoadError::Parse(error)
+    }
+}
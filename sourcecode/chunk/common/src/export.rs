@@ -0,0 +1,213 @@
+//! Exports chunks as Wavefront OBJ models, e.g. for viewing them in Blender.
+//!
+//! Every texture tile becomes its own material in the accompanying MTL file,
+//! pointing at the block texture it comes from.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    chunk::ChunkData,
+    chunkmesh::{ChunkMeshData, Lod, MeshingMode},
+    constants::SECTIONS_PER_CHUNK,
+    prelude::*,
+    texture::{TextureTile, MISSING_LAYER, TEXTURE_REGISTRY},
+};
+
+/// Turns `uv` clockwise around the middle of the tile, `turns` times,
+/// the same way the block shaders do.
+fn rotate_uv([u, v]: [f32; 2], turns: u8) -> [f32; 2] {
+    match turns {
+        1 => [v, 1.0 - u],
+        2 => [1.0 - u, 1.0 - v],
+        3 => [1.0 - v, u],
+        _ => [u, v],
+    }
+}
+
+/// Returns the size of every texture that's split into tiles, in pixels.
+///
+/// The textures themselves aren't loaded, so this assumes that the tiles of
+/// each texture reach its right and bottom edges, which they do for texture strips.
+fn texture_sizes(tiles: &[TextureTile]) -> HashMap<&str, [u32; 2]> {
+    let mut sizes: HashMap<&str, [u32; 2]> = HashMap::new();
+    for tile in tiles {
+        if let Some([_, _, x2, y2]) = tile.uv {
+            let size = sizes.entry(&tile.texture).or_default();
+            *size = [size[0].max(x2), size[1].max(y2)];
+        }
+    }
+    sizes
+}
+
+/// Returns the name of the material used for `tile`.
+fn material_name(tile: &TextureTile) -> String {
+    match tile.uv {
+        Some([x1, y1, ..]) => format!("{}_{}_{}", tile.texture, x1, y1),
+        None => tile.texture.clone(),
+    }
+}
+
+/// Builds the contents of an OBJ file containing `chunks`, along with the contents
+/// of the MTL file it uses, which it expects to be called `mtl_name`.
+///
+/// The chunks are placed where they are in the world, and faces between them are
+/// hidden like they are in game. Materials point at the block textures in `texture_dir`.
+///
+/// Light and ambient occlusion are written as vertex colors, which most tools
+/// (including Blender) understand even though they're not part of the OBJ standard.
+pub fn build_obj(chunks: &[&ChunkData], mtl_name: &str, texture_dir: &str) -> (String, String) {
+    let registry = TEXTURE_REGISTRY.get();
+    let tiles = registry.tiles();
+    let sizes = texture_sizes(tiles);
+    let loaded: HashMap<ChunkPos, &ChunkData> =
+        chunks.iter().map(|data| (data.position, *data)).collect();
+
+    let mut obj = String::new();
+    writeln!(obj, "mtllib {}", mtl_name).unwrap();
+    // The corners of every triangle, as indices into the vertex list, grouped by layer.
+    let mut triangles: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    let mut vertex_count = 0;
+    for chunk_data in chunks {
+        let origin = chunk_data.position.origin();
+        let others = loaded
+            .iter()
+            .filter(|(position, _)| **position != chunk_data.position)
+            .map(|(position, data)| (*position, *data))
+            .collect::<HashMap<_, _>>();
+        for section in 0..SECTIONS_PER_CHUNK {
+            let mesh = ChunkMeshData::new_from_section(
+                chunk_data,
+                section,
+                others.clone(),
+                MeshingMode::Naive,
+                Lod::Full,
+            );
+            for (_, surface) in mesh.surfaces() {
+                for i in 0..surface.vertices.len() {
+                    let [x, y, z] = surface.vertices[i];
+                    let [r, g, b, _] = surface.colors[i].map(|c| c as f32 / 255.0);
+                    writeln!(
+                        obj,
+                        "v {} {} {} {:.3} {:.3} {:.3}",
+                        x + origin.x as f32,
+                        y,
+                        z + origin.z as f32,
+                        r,
+                        g,
+                        b
+                    )
+                    .unwrap();
+                    let [nx, ny, nz] = surface.normals[i];
+                    writeln!(obj, "vn {} {} {}", nx, ny, nz).unwrap();
+
+                    let [layer, rotation] = surface.uv2s[i];
+                    let tile = &tiles[layer as usize];
+                    let [u, v] = rotate_uv(surface.uvs[i], rotation as u8);
+                    // Animated textures are exported as their first frame.
+                    let v = (tile.frame as f32 + v) / tile.frames as f32;
+                    let [u, v] = match (tile.uv, sizes.get(tile.texture.as_str())) {
+                        (Some([x1, y1, x2, y2]), Some([width, height])) => [
+                            (x1 as f32 + u * (x2 - x1) as f32) / *width as f32,
+                            (y1 as f32 + v * (y2 - y1) as f32) / *height as f32,
+                        ],
+                        _ => [u, v],
+                    };
+                    // OBJ's UVs start at the bottom of the texture, rather than the top.
+                    writeln!(obj, "vt {} {}", u, 1.0 - v).unwrap();
+                }
+                for index in &surface.indices {
+                    let [layer, _] = surface.uv2s[*index as usize];
+                    triangles
+                        .entry(layer as u32)
+                        .or_default()
+                        .push(vertex_count + *index as usize);
+                }
+                vertex_count += surface.vertices.len();
+            }
+        }
+    }
+
+    let mut mtl = String::new();
+    for (layer, corners) in &triangles {
+        let tile = &tiles[*layer as usize];
+        let name = if *layer == MISSING_LAYER {
+            "missing".to_string()
+        } else {
+            material_name(tile)
+        };
+        writeln!(obj, "usemtl {}", name).unwrap();
+        for triangle in corners.chunks(3) {
+            // OBJ indices start at 1.
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(obj, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c).unwrap();
+        }
+        writeln!(mtl, "newmtl {}", name).unwrap();
+        if *layer == MISSING_LAYER {
+            // The same colour as the placeholder drawn in game.
+            writeln!(mtl, "Kd 1.0 0.0 1.0").unwrap();
+            writeln!(mtl).unwrap();
+            continue;
+        }
+        writeln!(mtl, "Kd 1.0 1.0 1.0").unwrap();
+        writeln!(mtl, "map_Kd {}/{}.png", texture_dir, tile.texture).unwrap();
+        writeln!(mtl, "map_d {}/{}.png", texture_dir, tile.texture).unwrap();
+        writeln!(mtl).unwrap();
+    }
+    (obj, mtl)
+}
+
+/// Exports `chunks` to an OBJ file at `path`, with its materials in an MTL file
+/// next to it. See [`build_obj`].
+pub fn export_obj(chunks: &[&ChunkData], path: &Path, texture_dir: &str) -> io::Result<()> {
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid OBJ path"))?;
+    let (obj, mtl) = build_obj(chunks, mtl_name, texture_dir);
+    fs::write(path, obj)?;
+    fs::write(&mtl_path, mtl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BLOCK_MANAGER;
+
+    #[test]
+    fn test_build_obj() {
+        let mut chunks = [
+            ChunkData::new(ChunkPos::new(0, 0)),
+            ChunkData::new(ChunkPos::new(1, 0)),
+        ];
+        // Two blocks touching across the chunk border.
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        chunks[0].set(LocalBlockPos::new(31, 10, 0, ChunkPos::new(0, 0)), stone);
+        chunks[1].set(LocalBlockPos::new(0, 10, 0, ChunkPos::new(1, 0)), stone);
+        let (obj, mtl) = build_obj(&[&chunks[0], &chunks[1]], "region.mtl", "textures");
+
+        assert!(obj.starts_with("mtllib region.mtl\n"));
+        let count = |prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+        // The faces between the blocks are hidden.
+        assert_eq!(count("f "), 10 * 2);
+        assert_eq!(count("v "), 10 * 4);
+        assert_eq!(count("vt "), count("v "));
+        // The second block is placed in the second chunk.
+        assert!(obj.contains("v 33 "));
+
+        // Stone uses the top, side and bottom of its texture strip.
+        assert_eq!(count("usemtl "), 3);
+        assert!(mtl.contains("newmtl stone_16_0\n"));
+        assert!(mtl.contains("map_Kd textures/stone.png\n"));
+        for line in obj.lines().filter(|line| line.starts_with("vt ")) {
+            for uv in line.split(' ').skip(1) {
+                assert!((0.0..=1.0).contains(&uv.parse::<f32>().unwrap()));
+            }
+        }
+    }
+}
@@ -1,9 +1,18 @@
 pub mod block;
 pub mod chunk;
 pub mod chunkmesh;
+pub mod colormap;
 pub mod constants;
 pub mod errors;
+pub mod export;
+pub mod light;
 pub mod macros;
+pub mod model;
 pub mod network;
+pub mod performance;
+pub mod pool;
 pub mod positions;
 pub mod prelude;
+pub mod reloadable;
+pub mod texture;
+pub mod world;
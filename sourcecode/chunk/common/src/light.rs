@@ -0,0 +1,536 @@
+//! Light propagation through chunks.
+//!
+//! There are two separate light channels: sky light, which shines down from
+//! the top of the world, and block light, which is emitted by blocks like torches.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{block::BLOCK_MANAGER, chunk::ChunkData, constants::*, prelude::*};
+
+/// The brightest a block can be lit.
+pub const MAX_LIGHT: u8 = 15;
+
+/// The directions light spreads in. Straight down is first.
+const DIRECTIONS: [[isize; 3]; 6] = [
+    [0, -1, 0],
+    [0, 1, 0],
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum LightChannel {
+    Sky,
+    Block,
+}
+
+impl LightChannel {
+    fn get(&self, chunk: &ChunkData, position: LocalBlockPos) -> u8 {
+        match self {
+            LightChannel::Sky => chunk.sky_light(position),
+            LightChannel::Block => chunk.block_light(position),
+        }
+    }
+    fn set(&self, chunk: &mut ChunkData, position: LocalBlockPos, level: u8) {
+        match self {
+            LightChannel::Sky => chunk.set_sky_light(position, level),
+            LightChannel::Block => chunk.set_block_light(position, level),
+        }
+    }
+}
+
+/// Returns how much light is lost when passing through `block_id`,
+/// on top of the usual one level per block.
+///
+/// Returns `None` if the block is opaque, and lets no light through.
+fn filter(block_id: BlockID) -> Option<u8> {
+    if block_id == 0 {
+        return Some(0);
+    }
+    match BLOCK_MANAGER.get().block_by_id(block_id) {
+        Some(block) if block.transparent => Some(block.light_filter.unwrap_or(0)),
+        _ => None,
+    }
+}
+
+/// Returns how much light `block_id` emits.
+pub fn emission(block_id: BlockID) -> u8 {
+    if block_id == 0 {
+        return 0;
+    }
+    BLOCK_MANAGER
+        .get()
+        .block_by_id(block_id)
+        .map_or(0, |block| block.light_emission.min(MAX_LIGHT))
+}
+
+/// A group of chunks that light can be propagated through.
+///
+/// Light spreads freely across chunk borders, as long as the chunk on
+/// the other side is part of the volume.
+pub struct LightVolume<'a> {
+    chunks: HashMap<ChunkPos, &'a mut ChunkData>,
+    /// Chunks that have had their light changed.
+    changed: HashSet<ChunkPos>,
+}
+
+impl<'a> LightVolume<'a> {
+    pub fn new(chunks: HashMap<ChunkPos, &'a mut ChunkData>) -> Self {
+        Self {
+            chunks,
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Returns the chunks that have had their light changed so far.
+    pub fn changed(&self) -> &HashSet<ChunkPos> {
+        &self.changed
+    }
+
+    fn block(&self, position: GlobalBlockPos) -> Option<BlockID> {
+        let position = LocalBlockPos::from(position);
+        self.chunks
+            .get(&position.chunk)
+            .map(|data| data.get(position))
+    }
+
+    fn light(&self, channel: LightChannel, position: GlobalBlockPos) -> Option<u8> {
+        let position = LocalBlockPos::from(position);
+        self.chunks
+            .get(&position.chunk)
+            .map(|data| channel.get(data, position))
+    }
+
+    fn set_light(&mut self, channel: LightChannel, position: GlobalBlockPos, level: u8) {
+        let position = LocalBlockPos::from(position);
+        if let Some(data) = self.chunks.get_mut(&position.chunk) {
+            if channel.get(data, position) != level {
+                channel.set(data, position, level);
+                self.changed.insert(position.chunk);
+            }
+        }
+    }
+
+    /// Returns the y-level of the highest non-air block at the global `x` and `z`.
+    ///
+    /// Returns `None` if the column is entirely air, or isn't part of this volume.
+    fn column_top(&self, x: isize, z: isize) -> Option<usize> {
+        let local = LocalBlockPos::from(GlobalBlockPos::new(x, 0, z));
+        let data = self.chunks.get(&local.chunk)?;
+        (0..CHUNK_SIZE_Y)
+            .rev()
+            .find(|y| data.terrain[[local.x, *y, local.z]] != 0)
+    }
+
+    /// Recalculates all of the light in the chunk at `position`, spreading it
+    /// into (and pulling it in from) neighbouring chunks in this volume.
+    pub fn light_chunk(&mut self, position: ChunkPos) {
+        let data = match self.chunks.get_mut(&position) {
+            Some(data) => data,
+            None => return,
+        };
+        data.lightlevel.fill(0);
+        self.changed.insert(position);
+
+        let origin = position.origin();
+        let mut sky_queue = VecDeque::new();
+        let mut block_queue = VecDeque::new();
+        for x in 0..CHUNK_SIZE_X as isize {
+            for z in 0..CHUNK_SIZE_Z as isize {
+                let (x, z) = (origin.x + x, origin.z + z);
+                // Light shines straight down from the sky until something stops it.
+                let mut level = MAX_LIGHT;
+                let mut floor = CHUNK_SIZE_Y;
+                for y in (0..CHUNK_SIZE_Y).rev() {
+                    let block_position = GlobalBlockPos::new(x, y as isize, z);
+                    match self.block(block_position).and_then(filter) {
+                        Some(amount) => level = level.saturating_sub(amount),
+                        None => break,
+                    }
+                    if level == 0 {
+                        break;
+                    }
+                    self.set_light(LightChannel::Sky, block_position, level);
+                    floor = y;
+                }
+                // Only the lit blocks that sit next to something that could be darker
+                // than them need to spread their light sideways.
+                let spread_top = [(1, 0), (-1, 0), (0, 1), (0, -1), (0, 0)]
+                    .iter()
+                    .filter_map(|(dx, dz)| self.column_top(x + dx, z + dz))
+                    .max();
+                if let Some(spread_top) = spread_top {
+                    for y in floor..=spread_top.min(CHUNK_SIZE_Y - 1) {
+                        sky_queue.push_back(GlobalBlockPos::new(x, y as isize, z));
+                    }
+                }
+
+                let top = self.column_top(x, z).unwrap_or(0);
+                for y in 0..=top {
+                    let block_position = GlobalBlockPos::new(x, y as isize, z);
+                    let emission = self.block(block_position).map_or(0, emission);
+                    if emission > 0 {
+                        self.set_light(LightChannel::Block, block_position, emission);
+                        block_queue.push_back(block_position);
+                    }
+                }
+            }
+        }
+
+        // Pull in light from the edges of neighbouring chunks.
+        for neighbour in position.adjacent() {
+            let neighbour_origin = neighbour.origin();
+            if !self.chunks.contains_key(&neighbour) {
+                continue;
+            }
+            let (dx, dz) = (neighbour.x - position.x, neighbour.z - position.z);
+            for i in 0..CHUNK_SIZE_X as isize {
+                // The column of the neighbour that touches this chunk.
+                let (x, z) = match (dx, dz) {
+                    (1, _) => (neighbour_origin.x, neighbour_origin.z + i),
+                    (-1, _) => (
+                        neighbour_origin.x + CHUNK_SIZE_X as isize - 1,
+                        neighbour_origin.z + i,
+                    ),
+                    (_, 1) => (neighbour_origin.x + i, neighbour_origin.z),
+                    _ => (
+                        neighbour_origin.x + i,
+                        neighbour_origin.z + CHUNK_SIZE_Z as isize - 1,
+                    ),
+                };
+                // Anything above both columns is already fully lit on both sides.
+                let top = self
+                    .column_top(x, z)
+                    .max(self.column_top(x - dx, z - dz))
+                    .unwrap_or(0);
+                for y in 0..CHUNK_SIZE_Y {
+                    let block_position = GlobalBlockPos::new(x, y as isize, z);
+                    if y <= top && self.light(LightChannel::Sky, block_position).unwrap_or(0) > 1 {
+                        sky_queue.push_back(block_position);
+                    }
+                    if self.light(LightChannel::Block, block_position).unwrap_or(0) > 1 {
+                        block_queue.push_back(block_position);
+                    }
+                }
+            }
+        }
+
+        self.propagate(LightChannel::Sky, sky_queue);
+        self.propagate(LightChannel::Block, block_queue);
+    }
+
+    /// Updates the light around `position`, whose block has just been changed.
+    ///
+    /// This uses the usual two-queue approach: first, any light that passed through
+    /// (or came from) `position` is removed, then the surrounding light is spread
+    /// back into the darkened area, along with any light the new block emits.
+    pub fn update_block(&mut self, position: GlobalBlockPos) {
+        let block_id = match self.block(position) {
+            Some(block_id) => block_id,
+            None => return,
+        };
+        for channel in [LightChannel::Sky, LightChannel::Block] {
+            let level = self.light(channel, position).unwrap_or(0);
+            self.set_light(channel, position, 0);
+            let mut refill = if level > 0 {
+                self.unpropagate(channel, VecDeque::from([(position, level)]))
+            } else {
+                VecDeque::new()
+            };
+            // Let light from the surrounding blocks back in.
+            refill.extend(
+                DIRECTIONS
+                    .iter()
+                    .filter_map(|direction| position.offset((*direction).into()).ok()),
+            );
+            if position.y == CHUNK_SIZE_Y as isize - 1 && channel == LightChannel::Sky {
+                // There's nothing above the top of the world to shine light down.
+                if filter(block_id) == Some(0) {
+                    self.set_light(channel, position, MAX_LIGHT);
+                    refill.push_back(position);
+                }
+            }
+            if channel == LightChannel::Block && emission(block_id) > 0 {
+                self.set_light(channel, position, emission(block_id));
+                refill.push_back(position);
+            }
+            self.propagate(channel, refill);
+        }
+    }
+
+    /// Spreads light outwards from every position in `queue`, until it runs out.
+    fn propagate(&mut self, channel: LightChannel, mut queue: VecDeque<GlobalBlockPos>) {
+        while let Some(position) = queue.pop_front() {
+            let level = match self.light(channel, position) {
+                Some(level) if level > 0 => level,
+                _ => continue,
+            };
+            for direction in DIRECTIONS {
+                let neighbour = match position.offset(direction.into()) {
+                    Ok(neighbour) => neighbour,
+                    Err(_) => continue,
+                };
+                let amount = match self.block(neighbour).and_then(filter) {
+                    Some(amount) => amount,
+                    // Opaque, or not loaded.
+                    None => continue,
+                };
+                let new_level = if channel == LightChannel::Sky
+                    && direction[1] == -1
+                    && level == MAX_LIGHT
+                    && amount == 0
+                {
+                    // Full sky light travels straight down without dimming.
+                    MAX_LIGHT
+                } else {
+                    level.saturating_sub(1 + amount)
+                };
+                if new_level > self.light(channel, neighbour).unwrap_or(MAX_LIGHT) {
+                    self.set_light(channel, neighbour, new_level);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Darkens everything that was lit by the (already darkened) positions in `queue`,
+    /// each of which is paired with the light level it used to have.
+    ///
+    /// Returns the positions bordering the darkened area that are still lit by
+    /// something else, which should be `propagate`d to fill the area back in.
+    /// Light sources inside the area are relit with their own light and returned
+    /// too, since nothing else would put it back.
+    fn unpropagate(
+        &mut self,
+        channel: LightChannel,
+        mut queue: VecDeque<(GlobalBlockPos, u8)>,
+    ) -> VecDeque<GlobalBlockPos> {
+        let mut refill = VecDeque::new();
+        while let Some((position, level)) = queue.pop_front() {
+            for direction in DIRECTIONS {
+                let neighbour = match position.offset(direction.into()) {
+                    Ok(neighbour) => neighbour,
+                    Err(_) => continue,
+                };
+                let neighbour_level = match self.light(channel, neighbour) {
+                    Some(neighbour_level) if neighbour_level > 0 => neighbour_level,
+                    _ => continue,
+                };
+                let sky_column = channel == LightChannel::Sky
+                    && direction[1] == -1
+                    && level == MAX_LIGHT
+                    && neighbour_level == MAX_LIGHT;
+                if neighbour_level < level || sky_column {
+                    // This neighbour got its light from `position`.
+                    self.set_light(channel, neighbour, 0);
+                    queue.push_back((neighbour, neighbour_level));
+                    let emitted = match channel {
+                        LightChannel::Block => self.block(neighbour).map_or(0, emission),
+                        LightChannel::Sky => 0,
+                    };
+                    if emitted > 0 {
+                        self.set_light(channel, neighbour, emitted);
+                        refill.push_back(neighbour);
+                    }
+                } else {
+                    refill.push_back(neighbour);
+                }
+            }
+        }
+        refill
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sky(data: &ChunkData, x: usize, y: usize, z: usize) -> u8 {
+        data.sky_light(LocalBlockPos::new(x, y, z, data.position))
+    }
+
+    fn block(data: &ChunkData, x: usize, y: usize, z: usize) -> u8 {
+        data.block_light(LocalBlockPos::new(x, y, z, data.position))
+    }
+
+    #[test]
+    fn test_skylight_shadow() {
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let mut data = ChunkData::new(ChunkPos::new(0, 0));
+        // A stone floor at y=10, with a stone roof at y=20 covering part of the chunk.
+        for x in 0..CHUNK_SIZE_X {
+            for z in 0..CHUNK_SIZE_Z {
+                data.terrain[[x, 10, z]] = stone;
+                if x < 16 {
+                    data.terrain[[x, 20, z]] = stone;
+                }
+            }
+        }
+        let mut chunks = HashMap::new();
+        chunks.insert(data.position, &mut data);
+        let mut volume = LightVolume::new(chunks);
+        volume.light_chunk(ChunkPos::new(0, 0));
+        assert!(volume.changed().contains(&ChunkPos::new(0, 0)));
+
+        assert_eq!(sky(&data, 20, 11, 5), MAX_LIGHT);
+        assert_eq!(sky(&data, 20, 400, 5), MAX_LIGHT);
+        assert_eq!(sky(&data, 5, 20, 5), 0);
+        assert_eq!(sky(&data, 5, 10, 5), 0);
+        // Light decays by one per block under the roof.
+        assert_eq!(sky(&data, 15, 15, 5), MAX_LIGHT - 1);
+        assert_eq!(sky(&data, 12, 15, 5), MAX_LIGHT - 4);
+        assert_eq!(sky(&data, 0, 15, 5), 0);
+    }
+
+    #[test]
+    fn test_skylight_across_chunks() {
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let mut first = ChunkData::new(ChunkPos::new(0, 0));
+        let mut second = ChunkData::new(ChunkPos::new(1, 0));
+        // Roof over the whole second chunk, with the first being open to the sky.
+        for x in 0..CHUNK_SIZE_X {
+            for z in 0..CHUNK_SIZE_Z {
+                second.terrain[[x, 20, z]] = stone;
+            }
+        }
+        let mut chunks = HashMap::new();
+        chunks.insert(first.position, &mut first);
+        chunks.insert(second.position, &mut second);
+        let mut volume = LightVolume::new(chunks);
+        volume.light_chunk(ChunkPos::new(0, 0));
+        volume.light_chunk(ChunkPos::new(1, 0));
+
+        assert_eq!(sky(&first, 31, 5, 0), MAX_LIGHT);
+        assert_eq!(sky(&second, 0, 5, 0), MAX_LIGHT - 1);
+        assert_eq!(sky(&second, 3, 5, 0), MAX_LIGHT - 4);
+    }
+
+    #[test]
+    fn test_skylight_attenuation() {
+        let mut data = ChunkData::new(ChunkPos::new(0, 0));
+        // A leaf canopy covering half of the chunk.
+        let leaves = BLOCK_MANAGER.get().block("chestnut_leaves").unwrap().id;
+        for x in 0..16 {
+            for z in 0..CHUNK_SIZE_Z {
+                data.terrain[[x, 30, z]] = leaves;
+            }
+        }
+        let mut chunks = HashMap::new();
+        chunks.insert(data.position, &mut data);
+        LightVolume::new(chunks).light_chunk(ChunkPos::new(0, 0));
+        assert_eq!(sky(&data, 5, 30, 5), MAX_LIGHT - 1);
+        assert_eq!(sky(&data, 5, 5, 5), MAX_LIGHT - 1);
+        assert_eq!(sky(&data, 20, 5, 5), MAX_LIGHT);
+    }
+
+    #[test]
+    fn test_emitters() {
+        let blocks = BLOCK_MANAGER.get();
+        let mut data = ChunkData::new(ChunkPos::new(0, 0));
+        let lantern = blocks.block("lantern").unwrap();
+        let emission = lantern.light_emission;
+        data.terrain[[16, 100, 16]] = lantern.id;
+        data.terrain[[4, 100, 16]] = blocks.block("torch").unwrap().id;
+        let mut chunks = HashMap::new();
+        chunks.insert(data.position, &mut data);
+        LightVolume::new(chunks).light_chunk(ChunkPos::new(0, 0));
+
+        assert_eq!(block(&data, 16, 100, 16), emission);
+        assert_eq!(block(&data, 18, 100, 16), emission - 2);
+        assert_eq!(block(&data, 16, 100, 20), emission - 4);
+
+        // Break the lantern.
+        data.terrain[[16, 100, 16]] = 0;
+        let mut chunks = HashMap::new();
+        chunks.insert(data.position, &mut data);
+        LightVolume::new(chunks).update_block(GlobalBlockPos::new(16, 100, 16));
+        // Only the torch's light is left.
+        assert_eq!(block(&data, 16, 100, 16), 2);
+        assert_eq!(block(&data, 18, 100, 16), 0);
+        assert_eq!(block(&data, 4, 100, 16), 14);
+        assert_eq!(block(&data, 6, 100, 16), 12);
+        assert_eq!(block(&data, 14, 100, 16), 4);
+
+        // A light source right next to a broken one keeps shining.
+        let torch = blocks.block("torch").unwrap();
+        data.terrain[[16, 100, 16]] = lantern.id;
+        data.terrain[[17, 100, 16]] = torch.id;
+        let mut chunks = HashMap::new();
+        chunks.insert(data.position, &mut data);
+        LightVolume::new(chunks).light_chunk(ChunkPos::new(0, 0));
+        data.terrain[[16, 100, 16]] = 0;
+        let mut chunks = HashMap::new();
+        chunks.insert(data.position, &mut data);
+        LightVolume::new(chunks).update_block(GlobalBlockPos::new(16, 100, 16));
+        assert_eq!(block(&data, 17, 100, 16), torch.light_emission);
+        assert_eq!(block(&data, 16, 100, 16), torch.light_emission - 1);
+        assert_eq!(block(&data, 20, 100, 16), torch.light_emission - 3);
+    }
+
+    #[test]
+    fn test_update_block() {
+        let stone = BLOCK_MANAGER.get().block("stone").unwrap().id;
+        let mut first = ChunkData::new(ChunkPos::new(0, 0));
+        let mut second = ChunkData::new(ChunkPos::new(1, 0));
+        // A closed box of stone straddling the chunk border, from x=28..=35 and y=10..=20.
+        for data in [&mut first, &mut second] {
+            let origin = data.position.origin();
+            for x in 0..CHUNK_SIZE_X {
+                for y in 10..=20 {
+                    for z in 0..=8 {
+                        let global_x = origin.x + x as isize;
+                        let inside =
+                            (29..=34).contains(&global_x) && y > 10 && y < 20 && z > 0 && z < 8;
+                        if (28..=35).contains(&global_x) && !inside {
+                            data.terrain[[x, y, z]] = stone;
+                        }
+                    }
+                }
+            }
+        }
+        let mut chunks = HashMap::new();
+        chunks.insert(first.position, &mut first);
+        chunks.insert(second.position, &mut second);
+        let mut volume = LightVolume::new(chunks);
+        volume.light_chunk(ChunkPos::new(0, 0));
+        volume.light_chunk(ChunkPos::new(1, 0));
+        assert_eq!(
+            volume.light(LightChannel::Sky, GlobalBlockPos::new(31, 15, 4)),
+            Some(0)
+        );
+
+        // Open up a hole in the roof, in the second chunk.
+        let hole = GlobalBlockPos::new(33, 20, 4);
+        volume.chunks.get_mut(&ChunkPos::new(1, 0)).unwrap().terrain[[1, 20, 4]] = 0;
+        volume.changed.clear();
+        volume.update_block(hole);
+        assert_eq!(
+            volume.light(LightChannel::Sky, GlobalBlockPos::new(33, 11, 4)),
+            Some(MAX_LIGHT)
+        );
+        assert_eq!(
+            volume.light(LightChannel::Sky, GlobalBlockPos::new(31, 11, 4)),
+            Some(MAX_LIGHT - 2)
+        );
+        assert!(volume.changed().contains(&ChunkPos::new(0, 0)));
+        assert!(volume.changed().contains(&ChunkPos::new(1, 0)));
+
+        // And close it again.
+        volume.chunks.get_mut(&ChunkPos::new(1, 0)).unwrap().terrain[[1, 20, 4]] = stone;
+        volume.update_block(hole);
+        assert_eq!(
+            volume.light(LightChannel::Sky, GlobalBlockPos::new(33, 11, 4)),
+            Some(0)
+        );
+        assert_eq!(
+            volume.light(LightChannel::Sky, GlobalBlockPos::new(31, 15, 4)),
+            Some(0)
+        );
+        assert_eq!(
+            volume.light(LightChannel::Sky, GlobalBlockPos::new(33, 21, 4)),
+            Some(MAX_LIGHT)
+        );
+    }
+}
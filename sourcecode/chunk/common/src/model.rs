@@ -0,0 +1,448 @@
+//! Block models, for blocks that aren't plain cubes (slabs, plants, fences, etc.)
+//!
+//! Models are defined in `block_models.json` out of boxes and freely placed quads,
+//! using coordinates from 0 to 16 within the block. Blocks use them by naming one
+//! in the `model` field of their entry in `blocks.json`.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    block::{BlockID, BlockManager, BLOCK_MANAGER},
+    reloadable::Reloadable,
+    texture::BlockFace,
+};
+
+/// The file next to a module's `blocks.json` that holds its models.
+pub const MODELS_FILE: &str = "block_models.json";
+
+/// The models of the embedded [`DEFAULT_MODULE`](crate::block::DEFAULT_MODULE).
+pub(crate) const MODELS_JSON: &str = include_str!("../../../VoxelGame/assets/block_models.json"); // HARDCODED
+
+/// The number of model units along each side of a block.
+const UNITS: f32 = 16.0;
+
+/// Which corners of a box make up each of its faces, in the same order as the
+/// corners of the mesher's cube faces. `true` means the box's `to` coordinate
+/// is used on that axis, `false` its `from` coordinate.
+const BOX_FACES: [(BlockFace, [[bool; 3]; 4]); 6] = [
+    (
+        BlockFace::Top,
+        [
+            [false, true, false],
+            [true, true, false],
+            [false, true, true],
+            [true, true, true],
+        ],
+    ),
+    (
+        BlockFace::Bottom,
+        [
+            [false, false, true],
+            [true, false, true],
+            [false, false, false],
+            [true, false, false],
+        ],
+    ),
+    (
+        BlockFace::North,
+        [
+            [true, true, false],
+            [false, true, false],
+            [true, false, false],
+            [false, false, false],
+        ],
+    ),
+    (
+        BlockFace::South,
+        [
+            [false, true, true],
+            [true, true, true],
+            [false, false, true],
+            [true, false, true],
+        ],
+    ),
+    (
+        BlockFace::East,
+        [
+            [true, true, true],
+            [true, true, false],
+            [true, false, true],
+            [true, false, false],
+        ],
+    ),
+    (
+        BlockFace::West,
+        [
+            [false, true, false],
+            [false, true, true],
+            [false, false, false],
+            [false, false, true],
+        ],
+    ),
+];
+
+/// One face of a box in a model definition.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+struct BoxFaceDefinition {
+    /// Which of the block's face textures to use. Defaults to the face's own.
+    texture: Option<BlockFace>,
+    /// The part of the texture to use, as `[u1, v1, u2, v2]` from 0 to 16.
+    ///
+    /// Defaults to the part of the texture the face covers, as if it were
+    /// part of a full block.
+    uv: Option<[f32; 4]>,
+    /// Whether to skip drawing this face when it's on the edge of the block
+    /// and covered by an opaque neighbour.
+    cull: bool,
+}
+
+impl Default for BoxFaceDefinition {
+    fn default() -> Self {
+        Self {
+            texture: None,
+            uv: None,
+            cull: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct BoxDefinition {
+    from: [f32; 3],
+    to: [f32; 3],
+    /// The faces of the box to draw. If this is missing, all six are drawn.
+    faces: Option<HashMap<BlockFace, BoxFaceDefinition>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct QuadDefinition {
+    /// The corners of the quad: top-left, top-right, bottom-left, then bottom-right,
+    /// as seen from the front.
+    vertices: [[f32; 3]; 4],
+    /// Which of the block's face textures to use.
+    texture: BlockFace,
+    /// The part of the texture to use, as `[u1, v1, u2, v2]` from 0 to 16.
+    #[serde(default = "default_uv")]
+    uv: [f32; 4],
+    /// Skip drawing the quad when the neighbour on this side is opaque.
+    cull: Option<BlockFace>,
+    /// Also draw the back of the quad.
+    #[serde(default)]
+    double_sided: bool,
+}
+
+fn default_uv() -> [f32; 4] {
+    [0.0, 0.0, UNITS, UNITS]
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct CollisionBoxDefinition {
+    from: [f32; 3],
+    to: [f32; 3],
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct ModelDefinition {
+    #[serde(default)]
+    boxes: Vec<BoxDefinition>,
+    #[serde(default)]
+    quads: Vec<QuadDefinition>,
+    /// The model's collision boxes. Defaults to the model's boxes.
+    collision: Option<Vec<CollisionBoxDefinition>>,
+}
+
+/// Converts a point in model units into block space, where blocks span
+/// `0..1` on the x and z axes, and `-1..0` on the y axis.
+fn to_block_space(point: [f32; 3]) -> [f32; 3] {
+    [point[0] / UNITS, point[1] / UNITS - 1.0, point[2] / UNITS]
+}
+
+/// A quad of a `BlockModel`, ready to be added to a mesh.
+#[derive(Clone, Debug)]
+pub struct ModelQuad {
+    /// The corners of the quad in block space, in the same order as
+    /// the corners of the mesher's cube faces.
+    pub corners: [[f32; 3]; 4],
+    pub normal: [f32; 3],
+    pub uvs: [[f32; 2]; 4],
+    /// Which of the block's face textures to use.
+    pub texture: BlockFace,
+    /// Skip drawing the quad when the neighbour on this side is opaque.
+    pub cull: Option<BlockFace>,
+}
+
+impl ModelQuad {
+    /// Creates a quad, working out its normal from its corners.
+    fn new(
+        corners: [[f32; 3]; 4],
+        uvs: [[f32; 2]; 4],
+        texture: BlockFace,
+        cull: Option<BlockFace>,
+    ) -> Self {
+        let a = [0, 1, 2].map(|axis| corners[2][axis] - corners[0][axis]);
+        let b = [0, 1, 2].map(|axis| corners[1][axis] - corners[0][axis]);
+        let normal = [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ];
+        let length = normal.iter().map(|n| n * n).sum::<f32>().sqrt();
+        Self {
+            corners,
+            normal: normal.map(|n| n / length),
+            uvs,
+            texture,
+            cull,
+        }
+    }
+
+    /// Returns the same quad, facing the other way.
+    fn reversed(&self) -> Self {
+        let [c0, c1, c2, c3] = self.corners;
+        let [uv0, uv1, uv2, uv3] = self.uvs;
+        Self {
+            corners: [c1, c0, c3, c2],
+            normal: self.normal.map(|n| -n),
+            uvs: [uv1, uv0, uv3, uv2],
+            texture: self.texture,
+            cull: self.cull,
+        }
+    }
+}
+
+/// An axis-aligned box in block space, as `[min, max]`.
+pub type CollisionBox = [[f32; 3]; 2];
+
+/// A block model, made of quads and collision boxes in block space.
+#[derive(Clone, Debug)]
+pub struct BlockModel {
+    pub quads: Vec<ModelQuad>,
+    pub collision: Vec<CollisionBox>,
+}
+
+impl BlockModel {
+    fn new(definition: ModelDefinition) -> Self {
+        let mut quads = Vec::new();
+        for model_box in &definition.boxes {
+            let all_faces = BlockFace::ALL
+                .into_iter()
+                .map(|face| (face, BoxFaceDefinition::default()))
+                .collect();
+            let faces = model_box.faces.as_ref().unwrap_or(&all_faces);
+            for (face, corner_use) in BOX_FACES {
+                let face_definition = match faces.get(&face) {
+                    Some(face_definition) => face_definition,
+                    None => continue,
+                };
+                let corners = corner_use.map(|use_to| {
+                    [0, 1, 2].map(|axis| {
+                        if use_to[axis] {
+                            model_box.to[axis]
+                        } else {
+                            model_box.from[axis]
+                        }
+                    })
+                });
+                let uvs = Self::box_face_uvs(face, corners, face_definition.uv);
+                let on_edge = match face {
+                    BlockFace::Top => model_box.to[1] >= UNITS,
+                    BlockFace::Bottom => model_box.from[1] <= 0.0,
+                    BlockFace::East => model_box.to[0] >= UNITS,
+                    BlockFace::West => model_box.from[0] <= 0.0,
+                    BlockFace::South => model_box.to[2] >= UNITS,
+                    BlockFace::North => model_box.from[2] <= 0.0,
+                };
+                let cull = if face_definition.cull && on_edge {
+                    Some(face)
+                } else {
+                    None
+                };
+                quads.push(ModelQuad::new(
+                    corners.map(to_block_space),
+                    uvs,
+                    face_definition.texture.unwrap_or(face),
+                    cull,
+                ));
+            }
+        }
+        for quad in &definition.quads {
+            let [u1, v1, u2, v2] = quad.uv.map(|uv| uv / UNITS);
+            let model_quad = ModelQuad::new(
+                quad.vertices.map(to_block_space),
+                [[u1, v1], [u2, v1], [u1, v2], [u2, v2]],
+                quad.texture,
+                quad.cull,
+            );
+            if quad.double_sided {
+                quads.push(model_quad.reversed());
+            }
+            quads.push(model_quad);
+        }
+        let collision = match definition.collision {
+            Some(collision) => collision
+                .iter()
+                .map(|collision_box| [collision_box.from, collision_box.to])
+                .collect(),
+            None => definition
+                .boxes
+                .iter()
+                .map(|model_box| [model_box.from, model_box.to])
+                .collect::<Vec<_>>(),
+        };
+        let collision = collision
+            .into_iter()
+            .map(|[from, to]| [to_block_space(from), to_block_space(to)])
+            .collect();
+        Self { quads, collision }
+    }
+
+    /// Works out the UVs of the corners of a box face.
+    ///
+    /// By default these are the same as they would be on a full block face,
+    /// but they can be remapped to a specific part of the texture with `uv`.
+    fn box_face_uvs(
+        face: BlockFace,
+        corners: [[f32; 3]; 4],
+        uv: Option<[f32; 4]>,
+    ) -> [[f32; 2]; 4] {
+        let [u1, v1, u2, v2] = uv.unwrap_or([0.0, 0.0, UNITS, UNITS]).map(|uv| uv / UNITS);
+        corners.map(|corner| {
+            let [x, y, z] = corner.map(|coordinate| coordinate / UNITS);
+            // Textures are upright, so v runs down the block.
+            let (u, v) = match face {
+                BlockFace::Top | BlockFace::Bottom => (x, z),
+                BlockFace::East | BlockFace::West => (z, 1.0 - y),
+                BlockFace::North | BlockFace::South => (x, 1.0 - y),
+            };
+            [u1 + u * (u2 - u1), v1 + v * (v2 - v1)]
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ModelsFile {
+    models: HashMap<String, ModelDefinition>,
+}
+
+/// Parses the contents of a `block_models.json` file.
+pub(crate) fn parse_models(
+    bytes: &[u8],
+) -> Result<HashMap<String, ModelDefinition>, serde_json::Error> {
+    let file: ModelsFile = serde_json::from_slice(bytes)?;
+    Ok(file.models)
+}
+
+/// Builds the block models from the `block_models.json` files of each module,
+/// and keeps track of which blocks use them.
+pub struct ModelManager {
+    /// The model used by each block that has one.
+    block_models: HashMap<BlockID, BlockModel>,
+}
+
+impl ModelManager {
+    /// Builds the models of the embedded blocks.
+    fn new() -> Self {
+        let models = parse_models(MODELS_JSON.as_bytes()).unwrap();
+        Self::from_definitions(&BLOCK_MANAGER.get(), &models)
+    }
+
+    /// Builds the model of every block in `blocks` that has one, out of `models`.
+    pub(crate) fn from_definitions(
+        blocks: &BlockManager,
+        models: &HashMap<String, ModelDefinition>,
+    ) -> Self {
+        let block_models = blocks
+            .blocks()
+            .filter_map(|block| {
+                let name = block.model.as_ref()?;
+                let definition = models
+                    .get(name)
+                    .unwrap_or_else(|| panic!("Block {} uses missing model {}", block.id, name));
+                Some((block.id, BlockModel::new(definition.clone())))
+            })
+            .collect();
+        Self { block_models }
+    }
+
+    /// Returns the model used by `block_id`, or `None` if it's a plain cube.
+    pub fn model(&self, block_id: BlockID) -> Option<&BlockModel> {
+        self.block_models.get(&block_id)
+    }
+}
+
+pub static MODEL_MANAGER: Reloadable<ModelManager> = Reloadable::new(ModelManager::new);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(json: &str) -> BlockModel {
+        BlockModel::new(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn test_box_model() {
+        let slab = model(r#"{ "boxes": [ { "from": [0, 0, 0], "to": [16, 8, 16] } ] }"#);
+        assert_eq!(slab.quads.len(), 6);
+        let top = slab
+            .quads
+            .iter()
+            .find(|quad| quad.texture == BlockFace::Top)
+            .unwrap();
+        assert_eq!(top.normal, [0.0, 1.0, 0.0]);
+        assert!(top.corners.iter().all(|corner| corner[1] == -0.5));
+        // The top of a slab is in the middle of the block, so it's never culled.
+        assert_eq!(top.cull, None);
+        let bottom = slab
+            .quads
+            .iter()
+            .find(|quad| quad.texture == BlockFace::Bottom)
+            .unwrap();
+        assert_eq!(bottom.normal, [0.0, -1.0, 0.0]);
+        assert_eq!(bottom.cull, Some(BlockFace::Bottom));
+        // The sides only use the bottom half of the texture.
+        let east = slab
+            .quads
+            .iter()
+            .find(|quad| quad.texture == BlockFace::East)
+            .unwrap();
+        assert_eq!(east.normal, [1.0, 0.0, 0.0]);
+        assert!(east.uvs.iter().all(|uv| uv[1] >= 0.5));
+        assert_eq!(slab.collision, vec![[[0.0, -1.0, 0.0], [1.0, -0.5, 1.0]]]);
+    }
+
+    #[test]
+    fn test_quad_model() {
+        let plant = model(
+            r#"{
+                "quads": [
+                    {
+                        "vertices": [[0, 16, 0], [16, 16, 16], [0, 0, 0], [16, 0, 16]],
+                        "texture": "north",
+                        "double_sided": true
+                    }
+                ],
+                "collision": []
+            }"#,
+        );
+        assert_eq!(plant.quads.len(), 2);
+        let [front, back] = [&plant.quads[0], &plant.quads[1]];
+        for axis in 0..3 {
+            assert!((front.normal[axis] + back.normal[axis]).abs() < 1e-6);
+        }
+        assert!(plant.collision.is_empty());
+    }
+
+    #[test]
+    fn test_model_manager() {
+        for block in BLOCK_MANAGER.get().blocks() {
+            assert_eq!(
+                block.model.is_some(),
+                MODEL_MANAGER.get().model(block.id).is_some()
+            );
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::io::{Error, ErrorKind};
+
 use serde::{de::DeserializeOwned, Serialize};
 
 pub fn encode_and_compress<T: Serialize>(data: T) -> Vec<u8> {
@@ -11,7 +13,14 @@ pub fn encode_and_compress<T: Serialize>(data: T) -> Vec<u8> {
 }
 
 pub fn decode_compressed<T: DeserializeOwned>(compressed: &[u8]) -> T {
+    try_decode_compressed(compressed).unwrap()
+}
+
+/// Like [`decode_compressed`], but returns an error if `compressed` is truncated
+/// or corrupt, e.g. when it's read from a file rather than sent by the server.
+pub fn try_decode_compressed<T: DeserializeOwned>(compressed: &[u8]) -> std::io::Result<T> {
     let mut decompressed = Vec::new();
-    lzzzz::lz4f::decompress_to_vec(compressed, &mut decompressed).unwrap();
-    bincode::deserialize(&decompressed).unwrap()
+    lzzzz::lz4f::decompress_to_vec(compressed, &mut decompressed)
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+    bincode::deserialize(&decompressed).map_err(|error| Error::new(ErrorKind::InvalidData, error))
 }
@@ -2,11 +2,18 @@
 
 use std::{fmt::Formatter, time::Duration};
 
+use crate::chunkmesh::raw_mesh::UPLOADED_VERTEX_SIZE;
+
 /// Helper struct for timing chunk/mesh generation performance.
+#[derive(Default)]
 pub struct Timings {
     // TODO: Untie this from chunk/mesh stuff specifically
     pub generate_chunk: Vec<Duration>,
     pub build_mesh: Vec<Duration>,
+    pub build_collision: Vec<Duration>,
+    /// How many bytes each mesh takes up on the GPU, followed by how many
+    /// it would if it wasn't indexed and had normals.
+    pub mesh_sizes: Vec<[usize; 2]>,
 }
 
 impl Timings {
@@ -14,6 +21,8 @@ impl Timings {
         Self {
             generate_chunk: Vec::new(),
             build_mesh: Vec::new(),
+            build_collision: Vec::new(),
+            mesh_sizes: Vec::new(),
         }
     }
 
@@ -27,10 +36,27 @@ impl std::fmt::Display for Timings {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let gen_chunk_avg = Self::average(&self.generate_chunk) / 1000.0;
         let bld_mesh_avg = Self::average(&self.build_mesh) / 1000.0;
+        let bld_collision_avg = Self::average(&self.build_collision) / 1000.0;
         write!(
             f,
-            "-- Timings (avg) --\n\tChunk generation : {:.3} ms\n\tMesh building    : {:.3} ms",
-            gen_chunk_avg, bld_mesh_avg
-        )
+            "-- Timings (avg) --\n\tChunk generation   : {:.3} ms\n\tMesh building      : {:.3} ms\n\tCollision building : {:.3} ms",
+            gen_chunk_avg, bld_mesh_avg, bld_collision_avg
+        )?;
+        if !self.mesh_sizes.is_empty() {
+            let [indexed, unindexed] = self
+                .mesh_sizes
+                .iter()
+                .fold([0, 0], |[a, b], [size_a, size_b]| [a + size_a, b + size_b]);
+            let meshes = self.mesh_sizes.len() as f64;
+            write!(
+                f,
+                "\n\tMesh upload size   : {:.1} KiB ({:.1} KiB unindexed with normals, {:.0}% smaller, {} B per vertex)",
+                indexed as f64 / meshes / 1024.0,
+                unindexed as f64 / meshes / 1024.0,
+                100.0 - indexed as f64 / unindexed.max(1) as f64 * 100.0,
+                UPLOADED_VERTEX_SIZE
+            )?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,366 @@
+//! A fixed pool of worker threads, for building chunk meshes in the background.
+//!
+//! Jobs are queued under a key, such as the section they rebuild. Queueing a job for
+//! a key that already has one waiting merges the two, and the waiting job with the
+//! highest priority (the lowest number) is always picked first.
+//!
+//! Every job is given a generation number when it's queued. A job's result is only
+//! applied if no newer job for the same key has started running since, so a slow job
+//! can never overwrite the result of one that was queued after it. Since the older
+//! job's result is thrown away, a job queued while another one for its key is
+//! running takes over whatever that one still had to do, see [`Job::take_over`].
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+};
+
+/// Work that can be queued in a `WorkerPool`.
+pub trait Job: Send + 'static {
+    /// What running the job produces.
+    type Output;
+    /// What a running job hands over to a newer one for the same key.
+    type Handover: Send;
+
+    /// Runs the job, on one of the pool's worker threads.
+    fn run(&self) -> Self::Output;
+    /// Applies `output`, unless it's been superseded by a newer job's.
+    ///
+    /// No other results are applied while this runs, so it should be quick.
+    fn apply(self, output: Self::Output);
+    /// Combines this job with `newer`, a job for the same key that was queued
+    /// while this one was still waiting.
+    fn merge(&mut self, newer: Self);
+    /// Returns the work that a newer job for the same key has to do as well,
+    /// in case it supersedes this one while it's running.
+    fn handover(&self) -> Self::Handover;
+    /// Takes over `handover` from an older job for the same key, which was
+    /// running when this one was queued.
+    fn take_over(&mut self, handover: &Self::Handover);
+}
+
+/// A job waiting in the queue.
+struct Queued<J> {
+    job: J,
+    priority: usize,
+    generation: u64,
+}
+
+/// The newest job that's started running for a key.
+struct Running<J: Job> {
+    generation: u64,
+    handover: J::Handover,
+}
+
+struct Queue<K, J: Job> {
+    jobs: HashMap<K, Queued<J>>,
+    /// Keys are removed once their newest job has finished, or they're forgotten.
+    running: HashMap<K, Running<J>>,
+    next_generation: u64,
+    /// Set when the pool is dropped, telling the workers to stop.
+    closed: bool,
+}
+
+struct Shared<K, J: Job> {
+    queue: Mutex<Queue<K, J>>,
+    /// Notified whenever a job is queued, or the pool is closed.
+    queued: Condvar,
+    /// Held while results are applied, so they're applied one at a time.
+    ///
+    /// This is always locked before `queue`, never after.
+    applying: Mutex<()>,
+}
+
+/// A fixed number of threads running prioritized, coalesced [`Job`]s.
+///
+/// Dropping the pool discards any jobs that haven't started yet, and waits for
+/// the ones that have to finish.
+pub struct WorkerPool<K, J: Job> {
+    shared: Arc<Shared<K, J>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<K, J> WorkerPool<K, J>
+where
+    K: Clone + Eq + Hash + Send + 'static,
+    J: Job,
+{
+    /// Starts a pool with `threads` worker threads.
+    pub fn new(threads: usize) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: HashMap::new(),
+                running: HashMap::new(),
+                next_generation: 1,
+                closed: false,
+            }),
+            queued: Condvar::new(),
+            applying: Mutex::new(()),
+        });
+        let workers = (0..threads)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || Self::work(&shared))
+            })
+            .collect();
+        Self { shared, workers }
+    }
+
+    /// Starts a pool with a worker thread for every CPU core but one,
+    /// leaving that one for the game itself.
+    pub fn with_available_threads() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        Self::new(threads.saturating_sub(1).max(1))
+    }
+
+    /// Queues `job` under `key`, merging it into the job already waiting there if any.
+    ///
+    /// Lower `priority` values are run first.
+    pub fn submit(&self, key: K, priority: usize, mut job: J) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let generation = queue.next_generation;
+        queue.next_generation += 1;
+        match queue.jobs.get_mut(&key) {
+            Some(queued) => {
+                queued.job.merge(job);
+                queued.priority = queued.priority.min(priority);
+                queued.generation = generation;
+            }
+            None => {
+                // A waiting job has already taken over from the running one.
+                if let Some(running) = queue.running.get(&key) {
+                    job.take_over(&running.handover);
+                }
+                queue.jobs.insert(
+                    key,
+                    Queued {
+                        job,
+                        priority,
+                        generation,
+                    },
+                );
+            }
+        }
+        self.shared.queued.notify_one();
+    }
+
+    /// Recalculates the priority of every waiting job, e.g. after the player has moved.
+    pub fn reprioritize(&self, priority: impl Fn(&K) -> usize) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        for (key, queued) in queue.jobs.iter_mut() {
+            queued.priority = priority(key);
+        }
+    }
+
+    /// Drops the job waiting under `key`, and throws away the result of the one
+    /// running for it, e.g. once the chunk it's for has been unloaded.
+    pub fn forget(&self, key: &K) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.jobs.remove(key);
+        queue.running.remove(key);
+    }
+
+    /// Returns how many jobs are waiting to be run.
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().jobs.len()
+    }
+
+    /// Runs jobs until the pool is closed.
+    fn work(shared: &Shared<K, J>) {
+        loop {
+            let (key, queued) = {
+                let mut queue = shared.queue.lock().unwrap();
+                loop {
+                    if queue.closed {
+                        return;
+                    }
+                    let next = queue
+                        .jobs
+                        .iter()
+                        .min_by_key(|(_, queued)| (queued.priority, queued.generation))
+                        .map(|(key, _)| key.clone());
+                    if let Some(key) = next {
+                        let queued = queue.jobs.remove(&key).unwrap();
+                        let running = Running {
+                            generation: queued.generation,
+                            handover: queued.job.handover(),
+                        };
+                        queue.running.insert(key.clone(), running);
+                        break (key, queued);
+                    }
+                    queue = shared.queued.wait(queue).unwrap();
+                }
+            };
+            let output = queued.job.run();
+            let _applying = shared.applying.lock().unwrap();
+            let newest = {
+                let mut queue = shared.queue.lock().unwrap();
+                let newest = queue
+                    .running
+                    .get(&key)
+                    .is_some_and(|running| running.generation == queued.generation);
+                if newest {
+                    queue.running.remove(&key);
+                }
+                newest
+            };
+            if newest {
+                queued.job.apply(output);
+            }
+        }
+    }
+}
+
+impl<K, J: Job> Drop for WorkerPool<K, J> {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.queued.notify_all();
+        for worker in self.workers.drain(..) {
+            // A worker only fails if one of its jobs panicked, which has
+            // already been reported by then.
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    use super::*;
+
+    /// Sends its values to `results` once applied, after waiting for `release`.
+    struct TestJob {
+        values: Vec<u32>,
+        started: Option<Sender<()>>,
+        release: Option<Mutex<Receiver<()>>>,
+        results: Sender<Vec<u32>>,
+    }
+
+    impl TestJob {
+        fn new(values: &[u32], results: &Sender<Vec<u32>>) -> Self {
+            Self {
+                values: values.to_vec(),
+                started: None,
+                release: None,
+                results: results.clone(),
+            }
+        }
+
+        /// Makes the job block once it has started, until the returned sender is used.
+        fn blocking(mut self) -> (Self, Receiver<()>, Sender<()>) {
+            let (started, on_start) = channel();
+            let (release, on_release) = channel();
+            self.started = Some(started);
+            self.release = Some(Mutex::new(on_release));
+            (self, on_start, release)
+        }
+    }
+
+    impl Job for TestJob {
+        type Output = Vec<u32>;
+        type Handover = Vec<u32>;
+
+        fn run(&self) -> Vec<u32> {
+            if let Some(started) = &self.started {
+                started.send(()).unwrap();
+            }
+            if let Some(release) = &self.release {
+                release.lock().unwrap().recv().unwrap();
+            }
+            self.values.clone()
+        }
+        fn apply(self, output: Vec<u32>) {
+            self.results.send(output).unwrap();
+        }
+        fn merge(&mut self, newer: Self) {
+            self.values.extend(newer.values);
+        }
+        fn handover(&self) -> Vec<u32> {
+            self.values.clone()
+        }
+        fn take_over(&mut self, handover: &Vec<u32>) {
+            self.values.extend(handover);
+        }
+    }
+
+    #[test]
+    fn test_priority_and_merging() {
+        let pool = WorkerPool::new(1);
+        let (results, applied) = channel();
+        // Keep the only worker busy while the other jobs are queued.
+        let (blocker, started, release) = TestJob::new(&[0], &results).blocking();
+        pool.submit("blocker", 0, blocker);
+        started.recv().unwrap();
+
+        pool.submit("far", 5, TestJob::new(&[1], &results));
+        pool.submit("near", 2, TestJob::new(&[2], &results));
+        pool.submit("far", 5, TestJob::new(&[3], &results));
+        assert_eq!(pool.pending(), 2);
+        // The player moved, so the far job is now the closest.
+        pool.reprioritize(|key| if *key == "far" { 1 } else { 2 });
+        release.send(()).unwrap();
+
+        let order: Vec<_> = applied.iter().take(3).collect();
+        assert_eq!(order, vec![vec![0], vec![1, 3], vec![2]]);
+    }
+
+    #[test]
+    fn test_stale_results() {
+        let pool = WorkerPool::new(2);
+        let (results, applied) = channel();
+        let (old, started, release) = TestJob::new(&[1], &results).blocking();
+        pool.submit("section", 0, old);
+        started.recv().unwrap();
+        // Queued after the first job started, so it can't be merged into it, but it
+        // takes over the first job's values instead.
+        pool.submit("section", 0, TestJob::new(&[2], &results));
+        assert_eq!(applied.recv().unwrap(), vec![2, 1]);
+
+        // The first job finishes last, and its result is thrown away.
+        release.send(()).unwrap();
+        drop(pool);
+        assert!(applied.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_taking_over() {
+        let pool = WorkerPool::new(1);
+        let (results, applied) = channel();
+        let (old, started, release) = TestJob::new(&[1], &results).blocking();
+        pool.submit("section", 0, old);
+        started.recv().unwrap();
+        // Both of these wait for the first job, so the second is merged into the
+        // first, which has already taken over the running job's values.
+        pool.submit("section", 0, TestJob::new(&[2], &results));
+        pool.submit("section", 0, TestJob::new(&[3], &results));
+        release.send(()).unwrap();
+
+        // Nothing newer had started when the first job finished, so it's applied too.
+        let order: Vec<_> = applied.iter().take(2).collect();
+        assert_eq!(order, vec![vec![1], vec![2, 1, 3]]);
+    }
+
+    #[test]
+    fn test_forget() {
+        let pool = WorkerPool::new(1);
+        let (results, applied) = channel();
+        let (old, started, release) = TestJob::new(&[1], &results).blocking();
+        pool.submit("unloaded", 0, old);
+        started.recv().unwrap();
+        pool.submit("unloaded", 0, TestJob::new(&[2], &results));
+        pool.submit("loaded", 1, TestJob::new(&[3], &results));
+        pool.forget(&"unloaded");
+        assert_eq!(pool.pending(), 1);
+        release.send(()).unwrap();
+
+        // Neither the running job's result nor the waiting one are applied.
+        assert_eq!(applied.recv().unwrap(), vec![3]);
+        // Nothing is kept around for keys once their jobs are done.
+        assert!(pool.shared.queue.lock().unwrap().running.is_empty());
+        drop(pool);
+        assert!(applied.try_recv().is_err());
+    }
+}
@@ -35,6 +35,19 @@ impl ChunkPos {
             ChunkPos::new(self.x, self.z - 1),
         ]
     }
+    /// Returns `ChunkPos` that are adjacent to this one, including diagonals.
+    pub fn surrounding(&self) -> [ChunkPos; 8] {
+        [
+            ChunkPos::new(self.x + 1, self.z),
+            ChunkPos::new(self.x - 1, self.z),
+            ChunkPos::new(self.x, self.z + 1),
+            ChunkPos::new(self.x, self.z - 1),
+            ChunkPos::new(self.x + 1, self.z + 1),
+            ChunkPos::new(self.x + 1, self.z - 1),
+            ChunkPos::new(self.x - 1, self.z + 1),
+            ChunkPos::new(self.x - 1, self.z - 1),
+        ]
+    }
 }
 
 impl std::fmt::Display for ChunkPos {
@@ -43,6 +56,26 @@ impl std::fmt::Display for ChunkPos {
     }
 }
 
+/// A vertical section of a chunk, which has its own mesh and collision shape.
+///
+/// Sections are `SECTION_SIZE_Y` blocks high, and `y` counts them from the
+/// bottom of the world, so section 0 holds y=`0..SECTION_SIZE_Y`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub struct SectionPos {
+    pub chunk: ChunkPos,
+    pub y: usize,
+}
+
+impl SectionPos {
+    pub fn new(chunk: ChunkPos, y: usize) -> Self {
+        Self { chunk, y }
+    }
+    /// Returns the range of (local) block y-levels inside this section.
+    pub fn block_range(&self) -> std::ops::Range<usize> {
+        self.y * SECTION_SIZE_Y..(self.y + 1) * SECTION_SIZE_Y
+    }
+}
+
 /// An offset from some block position. Does not represent anything
 /// specifically in local/global space.
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -134,15 +167,39 @@ impl LocalBlockPos {
         }
         borders
     }
+    /// Returns the section this block position is in.
+    pub fn section(&self) -> SectionPos {
+        SectionPos::new(self.chunk, self.y / SECTION_SIZE_Y)
+    }
+    /// Returns the sections whose meshes depend on this block: its own section,
+    /// the one above or below it if it's on the edge of its section, and the
+    /// same sections of any chunks it `borders`.
+    pub fn affected_sections(&self) -> Vec<SectionPos> {
+        let section = self.y / SECTION_SIZE_Y;
+        let mut sections_y = vec![section];
+        match self.y % SECTION_SIZE_Y {
+            0 if section > 0 => sections_y.push(section - 1),
+            y if y == SECTION_SIZE_Y - 1 && section < SECTIONS_PER_CHUNK - 1 => {
+                sections_y.push(section + 1)
+            }
+            _ => {}
+        }
+        let mut chunks = self.borders();
+        chunks.push(self.chunk);
+        chunks
+            .into_iter()
+            .flat_map(|chunk| sections_y.iter().map(move |y| SectionPos::new(chunk, *y)))
+            .collect()
+    }
 }
 
 impl From<GlobalBlockPos> for LocalBlockPos {
     fn from(global_position: GlobalBlockPos) -> Self {
         let chunk = global_position.chunk();
         let chunk_origin = chunk.origin();
-        let x = (global_position.x - chunk_origin.x).abs() as usize;
-        let y = global_position.y.abs() as usize;
-        let z = (global_position.z - chunk_origin.z).abs() as usize;
+        let x = (global_position.x - chunk_origin.x).unsigned_abs();
+        let y = global_position.y.unsigned_abs();
+        let z = (global_position.z - chunk_origin.z).unsigned_abs();
         Self::new(x, y, z, chunk)
     }
 }
@@ -339,6 +396,45 @@ mod tests {
         lbp_to_gbp_test!([0, 0, 0], [-1, -1], [-32, 0, -32]);
     }
 
+    #[test]
+    fn test_chunk_surrounding() {
+        let surrounding = ChunkPos::new(0, 0).surrounding();
+        assert_eq!(surrounding.len(), 8);
+        for x in -1..=1 {
+            for z in -1..=1 {
+                let position = ChunkPos::new(x, z);
+                assert_eq!(surrounding.contains(&position), (x, z) != (0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_affected_sections() {
+        let chunk = ChunkPos::new(0, 0);
+        let sections = LocalBlockPos::new(5, 40, 5, chunk).affected_sections();
+        assert_eq!(sections, vec![SectionPos::new(chunk, 1)]);
+
+        // On the bottom edge of a section, next to the chunk at X-.
+        let sections = LocalBlockPos::new(0, 64, 5, chunk).affected_sections();
+        assert_eq!(sections.len(), 4);
+        for y in [1, 2] {
+            assert!(sections.contains(&SectionPos::new(chunk, y)));
+            assert!(sections.contains(&SectionPos::new(ChunkPos::new(-1, 0), y)));
+        }
+
+        // There's nothing below the bottom section or above the top one.
+        assert_eq!(
+            LocalBlockPos::new(5, 0, 5, chunk).affected_sections().len(),
+            1
+        );
+        let top = LocalBlockPos::new(5, CHUNK_SIZE_Y - 1, 31, chunk).affected_sections();
+        assert_eq!(top.len(), 2);
+        assert!(top.contains(&SectionPos::new(
+            ChunkPos::new(0, 1),
+            SECTIONS_PER_CHUNK - 1
+        )));
+    }
+
     // TODO: More tests
 
     #[test]
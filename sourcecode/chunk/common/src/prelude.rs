@@ -1,5 +1,5 @@
 pub use crate::{
     block::BlockID,
     constants::{CHUNK_SIZE_X, CHUNK_SIZE_Y, CHUNK_SIZE_Z},
-    positions::{ChunkPos, GlobalBlockPos, LocalBlockPos},
+    positions::{ChunkPos, GlobalBlockPos, LocalBlockPos, SectionPos},
 };
@@ -0,0 +1,117 @@
+//! Globals that are built the first time they're used, like `lazy_static`, but can
+//! be replaced or rebuilt later on, e.g. when the block definitions are reloaded.
+
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+use arc_swap::{ArcSwapOption, Guard};
+
+/// A global value, built by `build` the first time it's used.
+///
+/// [`Reloadable::get`] is cheap enough to call in the mesher's inner loops. Threads
+/// that are still using a replaced value keep it alive until they're done with it,
+/// and it's dropped after that.
+pub struct Reloadable<T> {
+    current: ArcSwapOption<T>,
+    /// Held while building, so that the value is only built once.
+    building: Mutex<()>,
+    build: fn() -> T,
+}
+
+/// A handle to the value a [`Reloadable`] held when it was fetched.
+pub struct Current<T> {
+    guard: Guard<Option<Arc<T>>>,
+}
+
+impl<T> Deref for Current<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_deref().unwrap()
+    }
+}
+
+impl<T> Reloadable<T> {
+    pub const fn new(build: fn() -> T) -> Self {
+        Self {
+            current: ArcSwapOption::const_empty(),
+            building: Mutex::new(()),
+            build,
+        }
+    }
+
+    /// Returns the current value, building it first if there isn't one.
+    ///
+    /// If the value is replaced, handles to the old one keep working, so hold onto
+    /// one handle rather than calling this again when several things need to agree
+    /// with each other.
+    pub fn get(&self) -> Current<T> {
+        let current = self.current.load();
+        if current.is_some() {
+            return Current { guard: current };
+        }
+        drop(current);
+        let _building = self.building.lock().unwrap();
+        // Another thread may have built it while waiting for the lock.
+        if self.current.load().is_none() {
+            self.set((self.build)());
+        }
+        Current {
+            guard: self.current.load(),
+        }
+    }
+
+    /// Replaces the value with `value`.
+    pub fn set(&self, value: T) {
+        self.current.store(Some(value.into()));
+    }
+
+    /// Throws away the value, so that it's built again the next time it's used.
+    pub fn reset(&self) {
+        self.current.store(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    static BUILDS: AtomicU32 = AtomicU32::new(0);
+    static DROPS: AtomicU32 = AtomicU32::new(0);
+
+    #[derive(Debug)]
+    struct Value(u32);
+
+    impl Drop for Value {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn build() -> Value {
+        Value(BUILDS.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    #[test]
+    fn test_reloadable() {
+        let value = Reloadable::new(build);
+        assert_eq!(value.get().0, 1);
+        // Only built once.
+        assert_eq!(value.get().0, 1);
+        let old = value.get();
+        value.set(Value(10));
+        assert_eq!(value.get().0, 10);
+        // Handles to the old value still work, and keep it alive until they're dropped.
+        assert_eq!(old.0, 1);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+        drop(old);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        value.reset();
+        assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+        assert_eq!(value.get().0, 2);
+    }
+}
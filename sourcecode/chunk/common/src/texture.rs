@@ -0,0 +1,539 @@
+//! Block texture definitions and layout.
+//!
+//! Blocks declare which texture each of their faces uses in `blocks.json`, and every
+//! distinct texture tile gets its own layer in a single texture array shared by
+//! all chunk meshes.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    block::{Block, BlockID, BLOCK_MANAGER, DEFAULT_MODULE},
+    reloadable::Reloadable,
+};
+
+/// The faces of a block.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockFace {
+    /// Y+
+    Top,
+    /// Y-
+    Bottom,
+    /// Z-
+    North,
+    /// Z+
+    South,
+    /// X+
+    East,
+    /// X-
+    West,
+}
+
+impl BlockFace {
+    pub const ALL: [BlockFace; 6] = [
+        BlockFace::Top,
+        BlockFace::Bottom,
+        BlockFace::North,
+        BlockFace::South,
+        BlockFace::East,
+        BlockFace::West,
+    ];
+
+    /// Returns the face pointing in the direction of `normal`.
+    pub fn from_normal(normal: [isize; 3]) -> Self {
+        match normal {
+            [0, 1, 0] => BlockFace::Top,
+            [0, -1, 0] => BlockFace::Bottom,
+            [0, 0, -1] => BlockFace::North,
+            [0, 0, 1] => BlockFace::South,
+            [1, 0, 0] => BlockFace::East,
+            [-1, 0, 0] => BlockFace::West,
+            _ => panic!("{:?} isn't the normal of a block face", normal),
+        }
+    }
+
+    /// Returns the face pointing the other way.
+    pub fn opposite(&self) -> Self {
+        match self {
+            BlockFace::Top => BlockFace::Bottom,
+            BlockFace::Bottom => BlockFace::Top,
+            BlockFace::North => BlockFace::South,
+            BlockFace::South => BlockFace::North,
+            BlockFace::East => BlockFace::West,
+            BlockFace::West => BlockFace::East,
+        }
+    }
+
+    /// Returns the direction this face points in.
+    pub fn normal(&self) -> [isize; 3] {
+        match self {
+            BlockFace::Top => [0, 1, 0],
+            BlockFace::Bottom => [0, -1, 0],
+            BlockFace::North => [0, 0, -1],
+            BlockFace::South => [0, 0, 1],
+            BlockFace::East => [1, 0, 0],
+            BlockFace::West => [-1, 0, 0],
+        }
+    }
+}
+
+/// How an animated texture is played.
+///
+/// The frames are stacked from top to bottom in the part of the texture the face uses,
+/// each as tall as the others, and loop forever.
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+pub struct TextureAnimation {
+    /// How many frames there are.
+    pub frames: u32,
+    /// How long each frame is shown for, in seconds.
+    pub frame_time: f32,
+    /// Whether each frame fades into the next, rather than switching to it at once.
+    #[serde(default)]
+    pub interpolate: bool,
+}
+
+/// The texture used by a single block face.
+///
+/// In `blocks.json` this is either just the texture's name, or an object like
+/// `{ "texture": "debug", "rotation": 90, "uv": [0, 0, 16, 16] }`, which can also
+/// have an `"animation"`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "FaceTextureDefinition")]
+pub struct FaceTexture {
+    /// The texture's name, i.e. `assets/textures/blocks/{texture}.png`.
+    pub texture: String,
+    /// How far the texture is rotated clockwise, in degrees.
+    ///
+    /// This is rounded down to a multiple of 90.
+    pub rotation: u16,
+    /// The part of the texture to use, as `[x1, y1, x2, y2]` in pixels.
+    ///
+    /// If this is `None`, the whole texture is used.
+    pub uv: Option<[u32; 4]>,
+    /// How the texture is animated, or `None` if it's a still image.
+    pub animation: Option<TextureAnimation>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FaceTextureDefinition {
+    Name(String),
+    Full {
+        texture: String,
+        #[serde(default)]
+        rotation: u16,
+        uv: Option<[u32; 4]>,
+        animation: Option<TextureAnimation>,
+    },
+}
+
+impl FaceTexture {
+    /// Checks that this texture can be cut out of its image, returning what's wrong
+    /// with it if it can't.
+    pub fn check(&self) -> Result<(), &'static str> {
+        if let Some([x1, y1, x2, y2]) = self.uv {
+            if x2 <= x1 || y2 <= y1 {
+                return Err("its uv rectangle is empty or flipped");
+            }
+        }
+        if let Some(animation) = self.animation {
+            if animation.frames == 0 {
+                return Err("its animation has no frames");
+            }
+            // This also catches NaN.
+            if !(animation.frame_time > 0.0 && animation.frame_time.is_finite()) {
+                return Err("its animation's frame_time isn't a positive number of seconds");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<FaceTextureDefinition> for FaceTexture {
+    fn from(definition: FaceTextureDefinition) -> Self {
+        match definition {
+            FaceTextureDefinition::Name(texture) => Self {
+                texture,
+                rotation: 0,
+                uv: None,
+                animation: None,
+            },
+            FaceTextureDefinition::Full {
+                texture,
+                rotation,
+                uv,
+                animation,
+            } => Self {
+                texture,
+                rotation,
+                uv,
+                animation,
+            },
+        }
+    }
+}
+
+/// Every face uses the same texture.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllTextures {
+    pub all: FaceTexture,
+}
+
+/// The top and bottom have their own textures, and the four sides share one.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopBottomSideTextures {
+    pub top: FaceTexture,
+    pub bottom: FaceTexture,
+    pub side: FaceTexture,
+}
+
+/// Every face has its own texture.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SixTextures {
+    pub top: FaceTexture,
+    pub bottom: FaceTexture,
+    pub north: FaceTexture,
+    pub south: FaceTexture,
+    pub east: FaceTexture,
+    pub west: FaceTexture,
+}
+
+/// The textures of every face of a block, as declared in `blocks.json`.
+///
+/// Each layout only accepts its own fields, so textures that could match more than
+/// one of them, or that have a misspelled face, don't parse.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum BlockTextures {
+    All(AllTextures),
+    TopBottomSide(TopBottomSideTextures),
+    Six(SixTextures),
+}
+
+impl BlockTextures {
+    /// The textures used by blocks that don't declare any: a strip named after the
+    /// block, with the top, side and bottom textures side by side.
+    ///
+    /// Blocks from modules other than [`DEFAULT_MODULE`] look for their strip in
+    /// a directory named after their module, like `rocks/stone` for `rocks:stone`.
+    fn strip(block: &Block) -> Self {
+        let texture = if block.key.starts_with(&format!("{}:", DEFAULT_MODULE)) {
+            block.local_name().to_string()
+        } else {
+            block.key.replacen(':', "/", 1)
+        };
+        let tile = |i: u32| FaceTexture {
+            texture: texture.clone(),
+            rotation: 0,
+            uv: Some([i * 16, 0, (i + 1) * 16, 16]),
+            animation: None,
+        };
+        BlockTextures::TopBottomSide(TopBottomSideTextures {
+            top: tile(0),
+            side: tile(1),
+            bottom: tile(2),
+        })
+    }
+
+    /// Returns the texture used by `face`.
+    pub fn face(&self, face: BlockFace) -> &FaceTexture {
+        match self {
+            BlockTextures::All(AllTextures { all }) => all,
+            BlockTextures::TopBottomSide(TopBottomSideTextures { top, bottom, side }) => match face
+            {
+                BlockFace::Top => top,
+                BlockFace::Bottom => bottom,
+                _ => side,
+            },
+            BlockTextures::Six(SixTextures {
+                top,
+                bottom,
+                north,
+                south,
+                east,
+                west,
+            }) => match face {
+                BlockFace::Top => top,
+                BlockFace::Bottom => bottom,
+                BlockFace::North => north,
+                BlockFace::South => south,
+                BlockFace::East => east,
+                BlockFace::West => west,
+            },
+        }
+    }
+}
+
+/// A part of a texture that takes up one layer of the texture array.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TextureTile {
+    /// The texture's name, i.e. `assets/textures/blocks/{texture}.png`.
+    pub texture: String,
+    /// The part of the texture to use, as `[x1, y1, x2, y2]` in pixels,
+    /// or `None` for the whole texture.
+    pub uv: Option<[u32; 4]>,
+    /// Which frame of an animation this tile is, counting from the top of `uv`.
+    pub frame: u32,
+    /// How many frames `uv` is split into, which is 1 for still textures.
+    pub frames: u32,
+}
+
+/// Where a block face's texture is in the texture array, and how it's rotated.
+///
+/// The frames of animated textures are on consecutive layers, starting at `layer`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct TextureLayer {
+    pub layer: u32,
+    /// How many times the texture is turned 90 degrees clockwise, from 0 to 3.
+    pub rotation: u8,
+}
+
+impl TextureLayer {
+    /// The layer drawn for faces of blocks that have no textures, like ones whose
+    /// numeric ID isn't in use.
+    pub const MISSING: TextureLayer = TextureLayer {
+        layer: MISSING_LAYER,
+        rotation: 0,
+    };
+}
+
+/// The layer of the texture array that no block uses, which holds a placeholder
+/// for faces without a texture rather than any of the block textures.
+pub const MISSING_LAYER: u32 = 0;
+
+/// Maps the faces of every block to layers of the block texture array.
+pub struct TextureRegistry {
+    /// Every tile in the texture array, in layer order. The one on [`MISSING_LAYER`]
+    /// has an empty texture name.
+    tiles: Vec<TextureTile>,
+    faces: HashMap<BlockID, [TextureLayer; 6]>,
+    /// The animation of each animated texture, by the layer of its first frame.
+    animations: HashMap<u32, TextureAnimation>,
+}
+
+impl TextureRegistry {
+    fn new<'a>(blocks: impl Iterator<Item = &'a Block>) -> Self {
+        let mut blocks: Vec<&Block> = blocks
+            // Air is never drawn.
+            .filter(|block| block.id != 0)
+            .collect();
+        blocks.sort_unstable_by_key(|block| block.id);
+
+        let mut tiles = vec![TextureTile {
+            texture: String::new(),
+            uv: None,
+            frame: 0,
+            frames: 1,
+        }];
+        // Tiles are only shared by faces that animate them the same way too, since
+        // each animation is stored by the layer of its first frame.
+        let mut layers: HashMap<(TextureTile, Option<(u32, bool)>), u32> = HashMap::new();
+        let mut faces = HashMap::new();
+        let mut animations = HashMap::new();
+        for block in blocks {
+            let textures = block
+                .textures
+                .clone()
+                .unwrap_or_else(|| BlockTextures::strip(block));
+            let block_faces = BlockFace::ALL.map(|face| {
+                let face_texture = textures.face(face);
+                let animation = face_texture.animation;
+                let tile = TextureTile {
+                    texture: face_texture.texture.clone(),
+                    uv: face_texture.uv,
+                    frame: 0,
+                    frames: animation.map_or(1, |animation| animation.frames.max(1)),
+                };
+                let key = (
+                    tile.clone(),
+                    animation
+                        .map(|animation| (animation.frame_time.to_bits(), animation.interpolate)),
+                );
+                let layer = *layers.entry(key).or_insert_with(|| {
+                    let first = tiles.len() as u32;
+                    if let Some(animation) = animation {
+                        animations.insert(first, animation);
+                    }
+                    tiles.extend((0..tile.frames).map(|frame| TextureTile {
+                        frame,
+                        ..tile.clone()
+                    }));
+                    first
+                });
+                TextureLayer {
+                    layer,
+                    rotation: (face_texture.rotation / 90 % 4) as u8,
+                }
+            });
+            faces.insert(block.id, block_faces);
+        }
+        Self {
+            tiles,
+            faces,
+            animations,
+        }
+    }
+
+    /// Returns where `face` of `block_id` is in the texture array.
+    pub fn layer(&self, block_id: BlockID, face: BlockFace) -> Option<TextureLayer> {
+        Some(self.faces.get(&block_id)?[face as usize])
+    }
+
+    /// Returns the animation whose first frame is on `layer`, or `None` if the
+    /// texture there is a still image.
+    pub fn animation(&self, layer: u32) -> Option<TextureAnimation> {
+        self.animations.get(&layer).copied()
+    }
+
+    /// Returns every tile in the texture array, in layer order.
+    pub fn tiles(&self) -> &[TextureTile] {
+        &self.tiles
+    }
+}
+
+pub static TEXTURE_REGISTRY: Reloadable<TextureRegistry> =
+    Reloadable::new(|| TextureRegistry::new(BLOCK_MANAGER.get().blocks()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_textures() {
+        let textures: BlockTextures = serde_json::from_str(r#"{ "all": "stone" }"#).unwrap();
+        assert_eq!(textures.face(BlockFace::East).texture, "stone");
+
+        let textures: BlockTextures = serde_json::from_str(
+            r#"{
+                "top": "grass_top",
+                "bottom": "dirt",
+                "side": { "texture": "grass_side", "rotation": 180, "uv": [0, 0, 16, 16] }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(textures.face(BlockFace::Top).texture, "grass_top");
+        assert_eq!(textures.face(BlockFace::Bottom).texture, "dirt");
+        let side = textures.face(BlockFace::North);
+        assert_eq!(side.texture, "grass_side");
+        assert_eq!(side.rotation, 180);
+        assert_eq!(side.uv, Some([0, 0, 16, 16]));
+
+        let textures: BlockTextures = serde_json::from_str(
+            r#"{
+                "top": "a", "bottom": "b",
+                "north": "c", "south": "d", "east": "e", "west": "f"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(textures.face(BlockFace::South).texture, "d");
+        assert_eq!(textures.face(BlockFace::West).texture, "f");
+
+        let textures: BlockTextures = serde_json::from_str(
+            r#"{ "all": {
+                "texture": "water",
+                "animation": { "frames": 4, "frame_time": 0.5 }
+            } }"#,
+        )
+        .unwrap();
+        let animation = textures.face(BlockFace::Top).animation.unwrap();
+        assert_eq!(animation.frames, 4);
+        assert_eq!(animation.frame_time, 0.5);
+        assert!(!animation.interpolate);
+
+        // Faces that don't belong to the layout, or are misspelled, aren't ignored.
+        for json in [
+            r#"{ "all": "stone", "top": "grass_top" }"#,
+            r#"{ "top": "a", "bottom": "b", "side": "c", "north": "d" }"#,
+            r#"{ "top": "a", "bottom": "b", "sides": "c" }"#,
+        ] {
+            assert!(
+                serde_json::from_str::<BlockTextures>(json).is_err(),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn test_animated_layers() {
+        let manager = crate::block::BlockManager::from_bytes(
+            br#"{ "module": "test", "blocks": {
+                "slow": { "name": "Slow", "durability": 1.0, "textures": { "all": {
+                    "texture": "lava", "animation": { "frames": 2, "frame_time": 2.0 }
+                } } },
+                "fast": { "name": "Fast", "durability": 1.0, "textures": { "all": {
+                    "texture": "lava", "animation": { "frames": 2, "frame_time": 0.5 }
+                } } },
+                "smooth": { "name": "Smooth", "durability": 1.0, "textures": { "all": {
+                    "texture": "lava",
+                    "animation": { "frames": 2, "frame_time": 0.5, "interpolate": true }
+                } } },
+                "also_slow": { "name": "Also Slow", "durability": 1.0, "textures": { "all": {
+                    "texture": "lava", "animation": { "frames": 2, "frame_time": 2.0 }
+                } } }
+            } }"#,
+        )
+        .unwrap();
+        let registry = TextureRegistry::new(manager.blocks());
+        let layer = |name: &str| {
+            let block_id = manager.block(&format!("test:{}", name)).unwrap().id;
+            registry.layer(block_id, BlockFace::Top).unwrap().layer
+        };
+        // The same frames played differently can't share layers.
+        assert_ne!(layer("slow"), layer("fast"));
+        assert_ne!(layer("fast"), layer("smooth"));
+        assert_eq!(layer("slow"), layer("also_slow"));
+        assert_eq!(registry.animation(layer("fast")).unwrap().frame_time, 0.5);
+        assert!(registry.animation(layer("smooth")).unwrap().interpolate);
+    }
+
+    #[test]
+    fn test_texture_registry() {
+        let blocks = BLOCK_MANAGER.get();
+        let registry = TEXTURE_REGISTRY.get();
+        assert_eq!(registry.layer(0, BlockFace::Top), None);
+        assert_eq!(registry.tiles()[MISSING_LAYER as usize].texture, "");
+        // Grass uses the default strip, whose sides all share a layer.
+        let grass = blocks.block("grass").unwrap().id;
+        let north = registry.layer(grass, BlockFace::North).unwrap();
+        assert_eq!(registry.layer(grass, BlockFace::East), Some(north));
+        assert_ne!(registry.layer(grass, BlockFace::Top), Some(north));
+        let tile = &registry.tiles()[north.layer as usize];
+        assert_eq!(tile.texture, "grass");
+        assert_eq!(tile.uv, Some([16, 0, 32, 16]));
+
+        // The debug block rotates some of its faces.
+        let debug = blocks.block("debug").unwrap().id;
+        let rotations = BlockFace::ALL.map(|face| registry.layer(debug, face).unwrap().rotation);
+        assert!(rotations.iter().any(|rotation| *rotation != 0));
+        assert!(rotations.iter().all(|rotation| *rotation < 4));
+
+        // Each frame of water's animation has its own layer, following the first.
+        let water = blocks.block("water").unwrap().id;
+        let water = registry.layer(water, BlockFace::Top).unwrap();
+        let animation = registry.animation(water.layer).unwrap();
+        for frame in 0..animation.frames {
+            let tile = &registry.tiles()[(water.layer + frame) as usize];
+            assert_eq!(tile.frame, frame);
+            assert_eq!(tile.frames, animation.frames);
+        }
+        assert_eq!(registry.animation(north.layer), None);
+
+        // No block uses the missing texture's layer.
+        for block in blocks.blocks().filter(|block| block.id != 0) {
+            for face in BlockFace::ALL {
+                let layer = registry.layer(block.id, face).unwrap();
+                assert_ne!(layer.layer, MISSING_LAYER);
+            }
+        }
+
+        // Tiles are never duplicated.
+        let tiles: std::collections::HashSet<_> = registry.tiles().iter().collect();
+        assert_eq!(tiles.len(), registry.tiles().len());
+    }
+}
@@ -0,0 +1,170 @@
+//! Saving a world's chunks to disk, and loading them again.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    block::{BlockIdTable, BlockManager, BLOCK_IDS_FILE},
+    chunk::ChunkData,
+    errors::BlockLoadError,
+    network::{encode_and_compress, try_decode_compressed},
+    positions::ChunkPos,
+};
+
+/// Writes `contents` to a temporary file next to `path`, then moves it into place,
+/// so that `path` is never left half written if the game stops partway through.
+pub(crate) fn write_atomically(
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+/// The chunks of a world saved in a directory, as `x_z.chunk` files in the same
+/// format they're sent to clients in, along with the [`BlockIdTable`] they use.
+///
+/// The world's table only ever grows, so every saved chunk keeps using it even when
+/// the loaded modules change. Chunks are remapped between it and the current IDs
+/// whenever they're loaded or saved.
+pub struct WorldSave {
+    directory: PathBuf,
+    ids: BlockIdTable,
+}
+
+impl WorldSave {
+    /// Opens the world saved in `directory`, which is created if it doesn't exist yet.
+    pub fn open(directory: impl AsRef<Path>) -> Result<Self, BlockLoadError> {
+        let directory = directory.as_ref().to_path_buf();
+        let ids = if directory.join(BLOCK_IDS_FILE).exists() {
+            BlockIdTable::load(&directory)?
+        } else {
+            fs::create_dir_all(&directory)?;
+            BlockIdTable::default()
+        };
+        Ok(Self { directory, ids })
+    }
+
+    fn chunk_path(&self, position: ChunkPos) -> PathBuf {
+        self.directory
+            .join(format!("{}_{}.chunk", position.x, position.z))
+    }
+
+    /// Loads the chunk at `position`, with its blocks remapped to their IDs in `blocks`.
+    ///
+    /// Returns `None` if the chunk was never saved, or an error if its file can't be
+    /// read or is corrupt. Blocks that no longer exist become air.
+    pub fn load_chunk(
+        &self,
+        position: ChunkPos,
+        blocks: &BlockManager,
+    ) -> Result<Option<ChunkData>, BlockLoadError> {
+        let bytes = match fs::read(self.chunk_path(position)) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let mut chunk: ChunkData = try_decode_compressed(&bytes)?;
+        chunk.remap_blocks(&blocks.remap_from(&self.ids));
+        Ok(Some(chunk))
+    }
+
+    /// Saves `chunks`, whose blocks have their IDs in `blocks`, replacing any
+    /// saved versions of them.
+    pub fn save_chunks<'a>(
+        &mut self,
+        chunks: impl IntoIterator<Item = &'a ChunkData>,
+        blocks: &BlockManager,
+    ) -> Result<(), BlockLoadError> {
+        // The table is written first, so that it always has every block the saved
+        // chunks use.
+        self.ids.extend(blocks)?;
+        self.ids.save(&self.directory)?;
+        let remap = blocks.remap_to(&self.ids);
+        for chunk in chunks {
+            let encoded = if remap.is_identity() {
+                encode_and_compress(chunk)
+            } else {
+                let mut saved = chunk.clone();
+                saved.remap_blocks(&remap);
+                encode_and_compress(&saved)
+            };
+            write_atomically(self.chunk_path(chunk.position), encoded)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        block::{BLOCKS_JSON, BLOCK_MANAGER},
+        positions::LocalBlockPos,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_world_save() {
+        let directory = std::env::temp_dir().join(format!("chunk_world_{}", std::process::id()));
+        let position = ChunkPos::new(3, -2);
+        let stone_position = LocalBlockPos::new(1, 2, 3, position);
+        let dirt_position = LocalBlockPos::new(4, 5, 6, position);
+        let blocks = BLOCK_MANAGER.get();
+        let mut chunk = ChunkData::new(position);
+        chunk.set(stone_position, blocks.block("stone").unwrap().id);
+        chunk.set(dirt_position, blocks.block("dirt").unwrap().id);
+        let mut world = WorldSave::open(&directory).unwrap();
+        world.save_chunks([&chunk], &blocks).unwrap();
+        assert!(world
+            .load_chunk(ChunkPos::new(0, 0), &blocks)
+            .unwrap()
+            .is_none());
+
+        // Adding a module changes the IDs, but the saved chunk keeps its blocks.
+        let addon: &[u8] = br#"{ "module": "addon", "blocks": {
+            "basalt": { "name": "Basalt", "durability": 2.0 }
+        } }"#;
+        let with_addon = BlockManager::from_modules(&[BLOCKS_JSON.as_bytes(), addon]).unwrap();
+        assert_ne!(
+            with_addon.block("stone").unwrap().id,
+            blocks.block("stone").unwrap().id
+        );
+        let mut world = WorldSave::open(&directory).unwrap();
+        let mut loaded = world.load_chunk(position, &with_addon).unwrap().unwrap();
+        assert_eq!(
+            loaded.get(stone_position),
+            with_addon.block("stone").unwrap().id
+        );
+        assert_eq!(
+            loaded.get(dirt_position),
+            with_addon.block("dirt").unwrap().id
+        );
+
+        // Saving with the addon's blocks adds them to the world's table without
+        // renumbering the others, and they become air once the addon is removed.
+        loaded.set(dirt_position, with_addon.block("addon:basalt").unwrap().id);
+        world.save_chunks([&loaded], &with_addon).unwrap();
+        let world = WorldSave::open(&directory).unwrap();
+        let reloaded = world.load_chunk(position, &blocks).unwrap().unwrap();
+
+        // Files cut short, e.g. by a crash while saving, are errors rather than panics.
+        let bytes = fs::read(world.chunk_path(position)).unwrap();
+        let truncated = ChunkPos::new(0, 1);
+        fs::write(world.chunk_path(truncated), &bytes[..bytes.len() / 2]).unwrap();
+        assert!(world.load_chunk(truncated, &blocks).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            reloaded.get(stone_position),
+            blocks.block("stone").unwrap().id
+        );
+        assert_eq!(reloaded.get(dirt_position), 0);
+        assert!(blocks.remap_to(&world.ids).is_identity());
+    }
+}
@@ -3,6 +3,7 @@
 use gdnative::{api::OpenSimplexNoise, prelude::Unique};
 
 use chunkcommon::{
+    block::BLOCK_MANAGER,
     chunk::ChunkData,
     constants::{CHUNK_SIZE_X, CHUNK_SIZE_Z},
     positions::{GlobalBlockPos, LocalBlockPos},
@@ -207,14 +208,25 @@ impl Feature for Trees {
             }
         }
 
+        let leaves = BLOCK_MANAGER.get().block("chestnut_leaves").unwrap().id;
+        let trunk = BLOCK_MANAGER.get().block("chestnut_wood").unwrap().id;
         for (x, z) in tree_positions {
             // Spawn trees where the ground turns to air.
             let air_start = chunk_data.get_air_start(x, z);
             match air_start {
                 Some(air_start) => {
+                    let ground = LocalBlockPos::new(x, air_start - 1, z, chunk_data.position);
+                    // Don't grow trees on top of water, or anything else that isn't solid ground.
+                    if BLOCK_MANAGER
+                        .get()
+                        .transparent_blocks
+                        .contains(&chunk_data.get(ground))
+                    {
+                        continue;
+                    }
                     let origin = LocalBlockPos::new(x, air_start, z, chunk_data.position);
-                    waitlist.merge(self.fill(chunk_data, origin, &Self::LEAVES, 24));
-                    waitlist.merge(self.fill(chunk_data, origin, &Self::TRUNK, 23));
+                    waitlist.merge(self.fill(chunk_data, origin, &Self::LEAVES, leaves));
+                    waitlist.merge(self.fill(chunk_data, origin, &Self::TRUNK, trunk));
                 }
                 None => {}
             };
@@ -2,26 +2,113 @@
 
 use gdnative::{api::OpenSimplexNoise, core_types::Vector2, object::Ref, prelude::Unique};
 
-use chunkcommon::{block::BLOCK_MANAGER, chunk::ChunkData, prelude::*, vec2};
+use chunkcommon::{
+    block::{BlockRemap, BLOCK_MANAGER},
+    chunk::ChunkData,
+    prelude::*,
+    vec2,
+};
 
 use crate::features::{trees::Trees, Feature, FeatureWaitlist};
 
 macro_rules! blockid {
     ($name:expr) => {
-        BLOCK_MANAGER.block($name).unwrap().id
+        BLOCK_MANAGER.get().block($name).unwrap().id
     };
 }
 
+/// A piecewise linear curve, used to map noise values onto terrain parameters.
+///
+/// Values outside of the curve's points are clamped to the first/last point.
+struct Spline {
+    points: Vec<(f64, f64)>,
+}
+
+impl Spline {
+    /// Creates a new `Spline`. `points` must be sorted by their first value.
+    fn new(points: &[(f64, f64)]) -> Self {
+        Self {
+            points: points.to_vec(),
+        }
+    }
+    fn sample(&self, at: f64) -> f64 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if at <= first.0 {
+            return first.1;
+        }
+        for window in self.points.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            if at <= end.0 {
+                let t = (at - start.0) / (end.0 - start.0);
+                return start.1 + (end.1 - start.1) * t;
+            }
+        }
+        last.1
+    }
+}
+
+/// Hermite interpolation between 0 and 1, for softening the edges of rivers.
+fn smoothstep(t: f64) -> f64 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Terrain information for a single x/z column.
+pub struct TerrainColumn {
+    /// The y-level of the column's topmost block.
+    peak: isize,
+    /// The block placed at `peak`.
+    surface: BlockID,
+    /// The column's temperature and humidity, from 0 to 255.
+    climate: [u8; 2],
+}
+
 struct GenerationConfig {
     top: BlockID,
     layers: Vec<(isize, BlockID)>,
     filler: BlockID,
     bottom: BlockID,
+    /// The chance of `bottom` generating at each y-level, starting from y=0.
+    ///
+    /// Anything above the last y-level here is never `bottom`.
+    bottom_layers: Vec<f64>,
+    water: BlockID,
+    river_bed: BlockID,
+    river_bank: BlockID,
+    /// Air below (and including) this y-level is filled with `water`.
+    sea_level: isize,
+    /// Maps continentalness noise to the terrain's base height.
+    continentalness: Spline,
+    /// Maps erosion noise to how far the terrain can stray from its base height.
+    /// Low erosion makes mountains, high erosion makes plains.
+    erosion: Spline,
+    /// How close to zero the river noise has to be for a river channel to be carved.
+    river_width: f64,
+    /// How far past `river_width` the banks of a river stretch.
+    bank_width: f64,
+    /// How far below `sea_level` the center of a river channel is.
+    river_depth: isize,
     features: Vec<Box<dyn Feature>>,
 }
 
+/// Creates an `OpenSimplexNoise` with the given parameters.
+fn create_noise(seed: i64, octaves: i64, period: f64) -> Ref<OpenSimplexNoise, Unique> {
+    let noise = OpenSimplexNoise::new();
+    // TODO: Real world seeds.
+    noise.set_seed(seed);
+    noise.set_octaves(octaves);
+    noise.set_period(period);
+    noise
+}
+
 pub struct ChunkGenerator {
     noise: Ref<OpenSimplexNoise, Unique>,
+    bottom_noise: Ref<OpenSimplexNoise, Unique>,
+    continentalness_noise: Ref<OpenSimplexNoise, Unique>,
+    erosion_noise: Ref<OpenSimplexNoise, Unique>,
+    river_noise: Ref<OpenSimplexNoise, Unique>,
+    temperature_noise: Ref<OpenSimplexNoise, Unique>,
+    humidity_noise: Ref<OpenSimplexNoise, Unique>,
     config: GenerationConfig,
     waitlist: FeatureWaitlist,
 }
@@ -31,25 +118,87 @@ impl ChunkGenerator {
         let layers = vec![(4, blockid!("dirt")), (8, blockid!("pebbled_dirt"))];
         Self {
             noise: OpenSimplexNoise::new(),
+            bottom_noise: create_noise(4, 1, 2.0),
+            continentalness_noise: create_noise(1, 4, 768.0),
+            erosion_noise: create_noise(2, 3, 384.0),
+            // A single octave keeps the rivers' paths smooth.
+            river_noise: create_noise(3, 1, 512.0),
+            temperature_noise: create_noise(5, 2, 1024.0),
+            humidity_noise: create_noise(6, 2, 1024.0),
             config: GenerationConfig {
                 top: blockid!("grass"),
                 layers,
                 filler: blockid!("stone"),
                 bottom: blockid!("silicate"),
+                bottom_layers: vec![1.0, 0.8, 0.6, 0.35, 0.15],
+                water: blockid!("water"),
+                river_bed: blockid!("gravel"),
+                river_bank: blockid!("sand"),
+                sea_level: 48,
+                continentalness: Spline::new(&[
+                    (-1.0, 24.0),
+                    (-0.3, 36.0),
+                    (-0.1, 50.0),
+                    (0.1, 56.0),
+                    (0.3, 72.0),
+                    (0.6, 120.0),
+                    (1.0, 160.0),
+                ]),
+                erosion: Spline::new(&[
+                    (-1.0, 140.0),
+                    (-0.4, 90.0),
+                    (-0.1, 40.0),
+                    (0.2, 16.0),
+                    (1.0, 4.0),
+                ]),
+                river_width: 0.03,
+                bank_width: 0.05,
+                river_depth: 5,
                 features: vec![Box::new(Trees::new())],
             },
             waitlist: FeatureWaitlist::new(),
         }
     }
-    pub fn generate_block(&self, y: isize, terrain_peak: isize) -> BlockID {
-        if y == terrain_peak {
-            self.config.top
-        } else if y == 0 {
+
+    /// Looks up the blocks the terrain is made of again, after the block definitions
+    /// have changed, and turns the IDs of the blocks still waiting to be placed into
+    /// the current ones with `remap`.
+    pub fn reload_blocks(&mut self, remap: &BlockRemap) {
+        self.config = Self::new().config;
+        for blocks in self.waitlist.chunks.values_mut() {
+            for (_, block_id) in blocks {
+                *block_id = remap.get(*block_id);
+            }
+        }
+    }
+    /// Returns `true` if `position` should be part of the bottom layers,
+    /// based on the probabilities in `GenerationConfig.bottom_layers`.
+    fn is_bottom(&self, position: GlobalBlockPos) -> bool {
+        let chance = match self.config.bottom_layers.get(position.y as usize) {
+            Some(chance) => *chance,
+            None => return false,
+        };
+        let noise =
+            self.bottom_noise
+                .get_noise_3d(position.x as f64, position.y as f64, position.z as f64);
+        // Roughly spread the noise out over 0..1 (it tends to bunch up around zero).
+        let roll = (noise * 1.5 + 0.5).clamp(0.0, 1.0);
+        chance >= 1.0 || roll < chance
+    }
+    pub fn generate_block(&self, position: GlobalBlockPos, column: &TerrainColumn) -> BlockID {
+        let y = position.y;
+        if y == column.peak {
+            column.surface
+        } else if y < column.peak && self.is_bottom(position) {
             self.config.bottom
-        } else if y > terrain_peak {
-            0
+        } else if y > column.peak {
+            if y <= self.config.sea_level {
+                self.config.water
+            } else {
+                0
+            }
         } else {
-            let distance_from_peak = terrain_peak - y;
+            let distance_from_peak = column.peak - y;
             for (gen_before, block_id) in &self.config.layers {
                 if distance_from_peak <= *gen_before {
                     return *block_id;
@@ -59,22 +208,68 @@ impl ChunkGenerator {
             self.config.filler
         }
     }
-    fn get_terrain_peak(&self, x: isize, z: isize) -> isize {
-        let noise_height: f64 = self.noise.get_noise_2dv(vec2!(x, z));
-        let peak = CHUNK_SIZE_Y as f64 * ((noise_height / 14.0) + 0.1);
-        peak as isize
+    fn get_terrain_column(&self, x: isize, z: isize) -> TerrainColumn {
+        let position = vec2!(x, z);
+        let config = &self.config;
+        let base_height = config
+            .continentalness
+            .sample(self.continentalness_noise.get_noise_2dv(position));
+        let roughness = config
+            .erosion
+            .sample(self.erosion_noise.get_noise_2dv(position));
+        let mut height = base_height + self.noise.get_noise_2dv(position) * roughness;
+        let mut surface = config.top;
+
+        // Rivers are carved along the ridges where the river noise crosses zero.
+        let river = self.river_noise.get_noise_2dv(position).abs();
+        let sea_level = config.sea_level as f64;
+        if river < config.river_width {
+            // The channel itself, deepest at its center.
+            let bed_height = (config.sea_level - config.river_depth) as f64;
+            let t = smoothstep(river / config.river_width);
+            height = height.min(bed_height + (sea_level - 1.0 - bed_height) * t);
+        } else if river < config.river_width + config.bank_width {
+            // The banks, sloping down from the surrounding terrain to the water.
+            let t = smoothstep((river - config.river_width) / config.bank_width);
+            height = height.min(sea_level + (height - sea_level) * t);
+            surface = config.river_bank;
+        }
+
+        let peak = height.max(1.0) as isize;
+        if peak < config.sea_level {
+            surface = config.river_bed;
+        } else if peak == config.sea_level && surface == config.top {
+            // Grass doesn't grow right next to the water.
+            surface = config.river_bank;
+        }
+        // Spread the noise out over 0..255, the same way as `is_bottom`.
+        let climate = [&self.temperature_noise, &self.humidity_noise].map(|noise| {
+            ((noise.get_noise_2dv(position) * 1.5 + 0.5).clamp(0.0, 1.0) * 255.0) as u8
+        });
+        TerrainColumn {
+            peak,
+            surface,
+            climate,
+        }
     }
     pub fn add_features(&mut self, chunk_data: &mut ChunkData) {
         for feature in &self.config.features {
             self.waitlist.merge(feature.add_to_chunk(chunk_data));
         }
     }
-    pub fn apply_waitlist_to(&mut self, data: &mut ChunkData) {
-        if let Some(add_blocks) = self.waitlist.chunks.remove(&data.position) {
-            for (pos, block_id) in add_blocks {
-                data.set(pos, block_id);
-            }
+    /// Sets any blocks waiting to be generated in `data`.
+    ///
+    /// Returns the positions of the blocks that were set.
+    pub fn apply_waitlist_to(&mut self, data: &mut ChunkData) -> Vec<LocalBlockPos> {
+        let add_blocks = self
+            .waitlist
+            .chunks
+            .remove(&data.position)
+            .unwrap_or_default();
+        for (pos, block_id) in &add_blocks {
+            data.set(*pos, *block_id);
         }
+        add_blocks.into_iter().map(|(pos, _)| pos).collect()
     }
     pub fn generate_chunk(&mut self, position: ChunkPos) -> ChunkData {
         let mut data = ChunkData::new(position);
@@ -85,20 +280,11 @@ impl ChunkGenerator {
             for z in 0..CHUNK_SIZE_Z {
                 let global_x = x as isize + chunk_origin.x;
                 let global_z = z as isize + chunk_origin.z;
-                let terrain_peak = self.get_terrain_peak(global_x, global_z);
-                for y in 0..CHUNK_SIZE_Y {
-                    data.terrain[[x, y, z]] = self.generate_block(y as isize, terrain_peak);
-                }
-            }
-        }
-
-        println!("Generating light level data for chunk {:?}", position);
-        // TODO: "update_lightlevel" in lib.rs
-        // TODO: use an unsigned 8 bit int!
-        for x in 0..CHUNK_SIZE_X {
-            for z in 0..CHUNK_SIZE_Z {
+                let column = self.get_terrain_column(global_x, global_z);
+                data.set_climate(x, z, column.climate);
                 for y in 0..CHUNK_SIZE_Y {
-                    data.skylightlevel[[x, y, z]] = 0;
+                    let position = GlobalBlockPos::new(global_x, y as isize, global_z);
+                    data.terrain[[x, y, z]] = self.generate_block(position, &column);
                 }
             }
         }
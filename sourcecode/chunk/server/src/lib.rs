@@ -1,18 +1,28 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Instant,
+};
 
 use crate::generate::ChunkGenerator;
 use chunkcommon::{
+    block::{self, BlockIdTable, BLOCK_MANAGER},
     chunk::ChunkData,
     chunkmesh::{
+        collision::ChunkCollisionData,
         nodes::{ChunkCollisionShape, ChunkNode},
-        ChunkMeshData,
     },
+    constants::SECTIONS_PER_CHUNK,
     errors::NotLoadedError,
+    export::export_obj,
+    light::LightVolume,
     network::encode_and_compress,
+    performance::Timings,
     prelude::*,
     vec2,
+    world::WorldSave,
 };
-use gdnative::prelude::*;
+use gdnative::{api::ProjectSettings, prelude::*};
 
 mod features;
 mod generate;
@@ -49,7 +59,7 @@ impl ServerChunk {
     fn new(data: ChunkData) -> Self {
         Self {
             data,
-            node: ChunkNode::new(None),
+            node: ChunkNode::new(false),
         }
     }
 }
@@ -61,6 +71,9 @@ pub struct ServerChunkCreator {
     base: Ref<Spatial, Shared>,
     chunks: HashMap<ChunkPos, ServerChunk>,
     chunk_generator: ChunkGenerator,
+    /// Where the world's chunks are saved, once it's been opened with `open_world_gd`.
+    world: Option<WorldSave>,
+    timings: Timings,
     // #[property]
     // initial_generation_area: Option<Rect2>,
 }
@@ -72,6 +85,8 @@ impl ServerChunkCreator {
             base: unsafe { base.assume_shared() },
             chunks: HashMap::new(),
             chunk_generator: ChunkGenerator::new(),
+            world: None,
+            timings: Timings::new(),
         }
     }
 
@@ -84,32 +99,58 @@ impl ServerChunkCreator {
         Some(chunk.data.get(position.into()))
     }
 
-    /// Sets a block in _global_ space.
+    /// Sets a block in _global_ space, updating the light around it.
+    ///
+    /// Returns the positions of the chunks that were changed, including those that
+    /// only had their light changed, so that they can be sent to clients again.
     ///
     /// Returns `NotLoadedError` if the block isn't loaded.
-    fn set_block(&mut self, position: GlobalBlockPos, to: BlockID) -> Result<(), NotLoadedError> {
-        // TODO: The collision shape needs to be updated here as well.
+    fn set_block(
+        &mut self,
+        position: GlobalBlockPos,
+        to: BlockID,
+    ) -> Result<HashSet<ChunkPos>, NotLoadedError> {
         let local_position: LocalBlockPos = position.into();
         let chunk = self
             .chunks
             .get_mut(&local_position.chunk)
             .ok_or(NotLoadedError)?;
-        // HARDCODED
-        // Don't allow for breaking silicate in any way.
-        if chunk.data.get(local_position) != 25 {
-            chunk.data.set(local_position, to);
+        let previous = chunk.data.get(local_position);
+        // Don't allow for breaking unbreakable blocks (like silicate) in any way.
+        if BLOCK_MANAGER
+            .get()
+            .block_by_id(previous)
+            .map_or(false, |block| block.unbreakable())
+        {
+            return Ok(HashSet::new());
         }
-        Ok(())
+        chunk.data.set(local_position, to);
+
+        let mut changed = self.update_block_light(position);
+        // Clients need to remesh the chunks next to the block too, if it's on an edge.
+        changed.extend(
+            local_position
+                .affected_sections()
+                .iter()
+                .map(|section| section.chunk),
+        );
+        self.update_sections([local_position.section()]);
+        Ok(changed)
     }
 
     #[export]
-    fn set_block_gd(&mut self, _base: &Spatial, position: Vector3, to: BlockID) {
+    /// Sets the block at `position` to `to`.
+    ///
+    /// Returns the positions of the chunks that need to be sent to clients again.
+    fn set_block_gd(&mut self, _base: &Spatial, position: Vector3, to: BlockID) -> Vec<Vector2> {
         let position = GlobalBlockPos::new(
             position.x as isize,
             position.y as isize,
             position.z as isize,
         );
-        self.set_block(position, to);
+        self.set_block(position, to)
+            .map(|changed| changed.iter().map(|pos| vec2!(pos.x, pos.z)).collect())
+            .unwrap_or_default()
     }
 
     #[export]
@@ -122,43 +163,143 @@ impl ServerChunkCreator {
         self.get_block(position)
     }
 
-    /// Returns a "view" into `ServerChunkCreator.chunks`, mapping `ChunkPos`s to `ChunkData`s.
-    fn data_view(&mut self) -> HashMap<ChunkPos, &ChunkData> {
-        self.chunks
-            .iter()
-            .map(|(pos, chunk)| (*pos, &chunk.data))
-            .collect()
+    /// Returns a `LightVolume` containing the chunk at `position` and all of the
+    /// (loaded) chunks surrounding it.
+    fn light_volume(&mut self, position: ChunkPos) -> LightVolume<'_> {
+        let surrounding = position.surrounding();
+        let chunks = self
+            .chunks
+            .iter_mut()
+            .filter(|(pos, _)| **pos == position || surrounding.contains(pos))
+            .map(|(pos, chunk)| (*pos, &mut chunk.data))
+            .collect();
+        LightVolume::new(chunks)
+    }
+
+    /// Recalculates the light levels of the chunk at `position`, going through its entire
+    /// terrain and lowering the light level depending on its distance from a light source or sky.
+    ///
+    /// Light is also spread into (and pulled in from) the chunks around it.
+    ///
+    /// Returns the positions of the chunks that had their light changed.
+    fn update_lightlevel(&mut self, position: ChunkPos) -> HashSet<ChunkPos> {
+        let mut light_volume = self.light_volume(position);
+        light_volume.light_chunk(position);
+        light_volume.changed().clone()
+    }
+
+    /// Incrementally updates the light around the block at `position`, after it has changed.
+    ///
+    /// Returns the positions of the chunks that had their light changed.
+    fn update_block_light(&mut self, position: GlobalBlockPos) -> HashSet<ChunkPos> {
+        let mut light_volume = self.light_volume(position.chunk());
+        light_volume.update_block(position);
+        light_volume.changed().clone()
+    }
+
+    /// Rebuilds the collision boxes of every section of the chunks at `positions`,
+    /// skipping any that aren't loaded.
+    fn update_collision(&mut self, positions: &[ChunkPos]) {
+        self.update_sections(
+            positions.iter().flat_map(|position| {
+                (0..SECTIONS_PER_CHUNK).map(|y| SectionPos::new(*position, y))
+            }),
+        );
+    }
+
+    /// Rebuilds the collision boxes of `sections`, skipping any that aren't loaded.
+    ///
+    /// The server never draws chunks, so it doesn't build their meshes at all.
+    fn update_sections(&mut self, sections: impl IntoIterator<Item = SectionPos>) {
+        for section in sections {
+            let chunk = match self.chunks.get_mut(&section.chunk) {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+            let start = Instant::now();
+            let collision_data = ChunkCollisionData::new_from_section(&chunk.data, section.y);
+            self.timings.build_collision.push(start.elapsed());
+            chunk.node.update_collision(section.y, &collision_data);
+        }
     }
 
     /// Loads a chunk from disk, or generates a new one.
-    fn load_chunk(&mut self, position: ChunkPos) -> ChunkData {
-        let data = if false {
-            todo!("Implement loading chunks from disk");
-        } else {
-            // The chunk is new.
-            self.chunk_generator.generate_chunk(position)
+    ///
+    /// Returns the chunk, along with the positions of any loaded chunks
+    /// that were changed by features spilling over into them.
+    fn load_chunk(&mut self, position: ChunkPos) -> (ChunkData, HashSet<ChunkPos>) {
+        let saved = self.world.as_ref().and_then(|world| {
+            world
+                .load_chunk(position, &BLOCK_MANAGER.get())
+                .unwrap_or_else(|error| {
+                    godot_warn!("Couldn't load chunk {:?}: {}", position, error);
+                    None
+                })
+        });
+        let data = match saved {
+            Some(data) => data,
+            None => {
+                // The chunk is new.
+                let start = Instant::now();
+                let data = self.chunk_generator.generate_chunk(position);
+                self.timings.generate_chunk.push(start.elapsed());
+                data
+            }
         };
-        for chunk in self.chunks.values_mut() {
-            self.chunk_generator.apply_waitlist_to(&mut chunk.data);
+        let mut changed = HashSet::new();
+        let loaded: Vec<ChunkPos> = self.chunks.keys().copied().collect();
+        for chunk_position in loaded {
+            let chunk = self.chunks.get_mut(&chunk_position).unwrap();
+            let placed = self.chunk_generator.apply_waitlist_to(&mut chunk.data);
+            if placed.is_empty() {
+                continue;
+            }
+            let mut sections = HashSet::new();
+            for block_position in placed {
+                changed.extend(self.update_block_light(block_position.into()));
+                changed.extend(
+                    block_position
+                        .affected_sections()
+                        .iter()
+                        .map(|section| section.chunk),
+                );
+                sections.insert(block_position.section());
+            }
+            self.update_sections(sections);
         }
-        data
+        (data, changed)
     }
 
     /// Takes ownership of `chunk` and adds it to the `ServerChunkCreator.chunks` HashMap.
     ///
     /// This allows other chunks to see it when making face calculations,
     /// and for functions such as `ServerChunkCreator.set_block` to be able to modify it.
-    fn add_chunk(&mut self, data: ChunkData) {
+    ///
+    /// Returns the positions of the chunks that had their light changed by the new chunk.
+    fn add_chunk(&mut self, data: ChunkData) -> HashSet<ChunkPos> {
         let position = data.position;
         let mut chunk = ServerChunk::new(data);
         chunk
             .node
             .spawn(&*unsafe { self.base.assume_safe() }, position);
-        chunk.node.update(&ChunkMeshData::new_from_chunk_data(
-            &chunk.data,
-            self.data_view(),
-        ));
         self.chunks.insert(position, chunk);
+        println!("Generating light level data for chunk {:?}", position);
+        let changed = self.update_lightlevel(position);
+        self.update_collision(&[position]);
+        changed
+    }
+
+    /// Loads the chunk at `position`, unless it's already loaded.
+    ///
+    /// Returns the positions of every chunk that was changed by loading it
+    /// (including itself), or `None` if it was already loaded.
+    fn load_new_chunk(&mut self, position: ChunkPos) -> Option<HashSet<ChunkPos>> {
+        if self.chunks.contains_key(&position) {
+            return None;
+        }
+        let (chunk, mut changed) = self.load_chunk(position);
+        changed.extend(self.add_chunk(chunk));
+        Some(changed)
     }
 
     #[export]
@@ -178,33 +319,213 @@ impl ServerChunkCreator {
     /// in *this session*, not whether it was loaded from the disk or not.
     fn load_chunk_gd(&mut self, _base: &Spatial, chunk_position: Vector2) -> bool {
         let position = ChunkPos::new(chunk_position.x as isize, chunk_position.y as isize);
-        if self.chunks.contains_key(&position) {
-            return false;
-        }
-        let chunk = self.load_chunk(position);
-        self.add_chunk(chunk);
-        true
+        self.load_new_chunk(position).is_some()
     }
 
     #[export]
     /// Loads a 4x4 square of chunks around `chunk_position`.
     ///
-    /// Returns the positions of those chunks.
-    fn load_around_chunk_gd(&mut self, base: &Spatial, chunk_position: Vector2) -> Vec<Vector2> {
+    /// Returns the positions of those chunks, along with any others that were
+    /// changed by loading them (e.g. by trees or light spilling over).
+    fn load_around_chunk_gd(&mut self, _base: &Spatial, chunk_position: Vector2) -> Vec<Vector2> {
         let chunk_position = ChunkPos::new(chunk_position.x as isize, chunk_position.y as isize);
         let mut around = Vec::new();
+        let mut changed = HashSet::new();
         for x in -2..=2 {
             for z in -2..=2 {
-                let chunk_pos = vec2!(chunk_position.x + x, chunk_position.z + z);
-                self.load_chunk_gd(base, chunk_pos);
-                around.push(chunk_pos);
+                let position = ChunkPos::new(chunk_position.x + x, chunk_position.z + z);
+                changed.extend(self.load_new_chunk(position).unwrap_or_default());
+                around.push(position);
             }
         }
+        changed.retain(|position| !around.contains(position));
         around
+            .into_iter()
+            .chain(changed)
+            .map(|position| vec2!(position.x, position.z))
+            .collect()
+    }
+
+    /// Returns the loaded chunks within `area`.
+    fn chunks_in(&self, area: Rect2) -> Vec<&ServerChunk> {
+        let range = PositionRange::from(area);
+        (range.x1..range.x2)
+            .flat_map(|x| (range.y1..range.y2).map(move |z| ChunkPos::new(x, z)))
+            .filter_map(|position| self.chunks.get(&position))
+            .collect()
+    }
+
+    #[export]
+    /// Exports the loaded chunks within `area` (in chunk coordinates) as an OBJ
+    /// model at `path`, with an MTL file next to it whose materials use the block
+    /// textures in `texture_dir`.
+    ///
+    /// Both paths may be Godot paths, such as `user://`. Returns `true` on success.
+    fn export_region_gd(
+        &self,
+        _base: &Spatial,
+        area: Rect2,
+        path: GodotString,
+        texture_dir: GodotString,
+    ) -> bool {
+        let settings = ProjectSettings::godot_singleton();
+        let path = settings.globalize_path(path).to_string();
+        let texture_dir = settings.globalize_path(texture_dir).to_string();
+        let chunks = self
+            .chunks_in(area)
+            .into_iter()
+            .map(|chunk| &chunk.data)
+            .collect::<Vec<_>>();
+        match export_obj(&chunks, Path::new(&path), &texture_dir) {
+            Ok(()) => true,
+            Err(error) => {
+                godot_warn!("Couldn't export chunks to {}: {}", path, error);
+                false
+            }
+        }
     }
 
-    // TODO: a "update_lightlevel" function, goes through the entire
-    // terrain info, lowering the light level depending on it's distance from a light source or sky
+    #[export]
+    /// Saves the loaded chunks within `area` (in chunk coordinates) to `directory`,
+    /// as `x_z.chunk` files in the same format they're sent to clients in, along with
+    /// the numeric ID of every block so they can be remapped if the blocks change.
+    ///
+    /// These can be exported later without running the game, using the `chunk-export`
+    /// tool in `chunkcommon`. Returns `true` on success.
+    fn save_region_gd(&self, _base: &Spatial, area: Rect2, directory: GodotString) -> bool {
+        let directory = ProjectSettings::godot_singleton()
+            .globalize_path(directory)
+            .to_string();
+        let chunks = self.chunks_in(area).into_iter().map(|chunk| &chunk.data);
+        let result = WorldSave::open(&directory)
+            .and_then(|mut world| world.save_chunks(chunks, &BLOCK_MANAGER.get()));
+        if let Err(error) = &result {
+            godot_warn!("Couldn't save chunks to {}: {}", directory, error);
+        }
+        result.is_ok()
+    }
+
+    #[export]
+    /// Opens the world saved in `directory`, which may be a Godot path such as
+    /// `user://`, so that chunks are loaded from it rather than generated, if they
+    /// were saved before. The directory is created if it doesn't exist yet.
+    ///
+    /// Returns `true` on success.
+    fn open_world_gd(&mut self, _base: &Spatial, directory: GodotString) -> bool {
+        let directory = ProjectSettings::godot_singleton()
+            .globalize_path(directory)
+            .to_string();
+        match WorldSave::open(&directory) {
+            Ok(world) => {
+                self.world = Some(world);
+                true
+            }
+            Err(error) => {
+                godot_warn!("Couldn't open the world in {}: {}", directory, error);
+                false
+            }
+        }
+    }
+
+    #[export]
+    /// Saves every loaded chunk to the world opened with `open_world_gd`.
+    ///
+    /// Returns `true` on success.
+    fn save_world_gd(&mut self, _base: &Spatial) -> bool {
+        let world = match &mut self.world {
+            Some(world) => world,
+            None => {
+                godot_warn!("Couldn't save the world, since none was opened");
+                return false;
+            }
+        };
+        let chunks = self.chunks.values().map(|chunk| &chunk.data);
+        if let Err(error) = world.save_chunks(chunks, &BLOCK_MANAGER.get()) {
+            godot_warn!("Couldn't save the world: {}", error);
+            return false;
+        }
+        true
+    }
+
+    #[export]
+    /// Returns the numeric ID of every block, as JSON, for clients to translate
+    /// the chunks they're sent into their own IDs.
+    fn block_ids_gd(&self, _base: &Spatial) -> String {
+        BLOCK_MANAGER.get().id_table().to_json()
+    }
+
+    #[export]
+    /// Returns the numeric ID of the block named `name`, like `basegame:grass`,
+    /// or `null` if there's no such block.
+    fn block_id_gd(&self, _base: &Spatial, name: String) -> Option<BlockID> {
+        BLOCK_MANAGER.get().block(&name).map(|block| block.id)
+    }
+
+    /// Switches the loaded chunks over to the current block definitions, after they've
+    /// changed from `old_ids`, and rebuilds their collision boxes.
+    ///
+    /// Returns the positions of the chunks whose blocks were renumbered, which need
+    /// to be sent to clients again along with the new IDs.
+    fn blocks_changed(&mut self, old_ids: &BlockIdTable) -> Vec<Vector2> {
+        let remap = BLOCK_MANAGER.get().remap_from(old_ids);
+        for chunk in self.chunks.values_mut() {
+            chunk.data.remap_blocks(&remap);
+        }
+        self.chunk_generator.reload_blocks(&remap);
+        let positions: Vec<ChunkPos> = self.chunks.keys().copied().collect();
+        self.update_collision(&positions);
+        if remap.is_identity() {
+            return Vec::new();
+        }
+        positions
+            .into_iter()
+            .map(|position| vec2!(position.x, position.z))
+            .collect()
+    }
+
+    #[export]
+    /// Loads the block definitions from the `blocks.json` file of each module at
+    /// `paths`, which may be Godot paths such as `res://`, instead of the ones built
+    /// into the game. The built-in `basegame` module is kept unless one of them
+    /// replaces it.
+    ///
+    /// Best called before any chunks are loaded, since clients need to be sent them
+    /// again. Returns the positions of those chunks on success, otherwise `null`, and
+    /// the current definitions are kept.
+    fn load_blocks_gd(&mut self, _base: &Spatial, paths: Vec<GodotString>) -> Option<Vec<Vector2>> {
+        let settings = ProjectSettings::godot_singleton();
+        let paths: Vec<String> = paths
+            .into_iter()
+            .map(|path| settings.globalize_path(path).to_string())
+            .collect();
+        let old_ids = BLOCK_MANAGER.get().id_table();
+        if let Err(error) = block::load_blocks(&paths) {
+            godot_warn!("Couldn't load blocks from {:?}: {}", paths, error);
+            return None;
+        }
+        Some(self.blocks_changed(&old_ids))
+    }
+
+    #[export]
+    /// Loads the block definitions again from the file they were last loaded from,
+    /// e.g. after editing it during development.
+    ///
+    /// Returns the positions of the chunks that need to be sent to clients again on
+    /// success, otherwise `null`, and the current definitions are kept.
+    fn reload_blocks_gd(&mut self, _base: &Spatial) -> Option<Vec<Vector2>> {
+        let old_ids = BLOCK_MANAGER.get().id_table();
+        if let Err(error) = block::reload_blocks() {
+            godot_warn!("Couldn't reload blocks: {}", error);
+            return None;
+        }
+        Some(self.blocks_changed(&old_ids))
+    }
+
+    #[export]
+    /// Prints the average time taken to generate chunks and build their meshes.
+    fn print_timings_gd(&self, _base: &Spatial) {
+        godot_print!("{}", self.timings);
+    }
 
     #[export]
     fn _ready(&mut self, _base: &Spatial) {